@@ -58,6 +58,11 @@ pub struct QuorumStoreConfig {
     pub sender_max_batch_txns: usize,
     /// The maximum number of bytes that the batch generator puts in a batch.
     pub sender_max_batch_bytes: usize,
+    /// The weight each transaction adds on top of its size when the batch generator fills a batch,
+    /// 0 to fill batches by size only. Batches then also stay within sender_max_batch_bytes of
+    /// weight, which limits batches of many small transactions that cost more to execute than
+    /// their size suggests.
+    pub sender_batch_txn_weight_overhead: usize,
     /// The maximum number of batches that the batch generator creates every time it pull transactions
     /// from the mempool. This is NOT the maximum number of batches that the batch generator can create
     /// per second.
@@ -69,6 +74,18 @@ pub struct QuorumStoreConfig {
     /// The maximum number of bytes that the batch generator pulls from the mempool at a time. This is NOT
     /// the maximum number of bytes the batch generator includes in batches per second.
     pub sender_max_total_bytes: usize,
+    /// The minimum number of transactions that the batch generator waits for when pulling from the
+    /// mempool, 0 to take whatever is available right away. Fills batches better under light load.
+    pub sender_min_pull_txns: usize,
+    /// The maximum time the mempool waits for sender_min_pull_txns transactions before responding
+    /// with fewer.
+    pub sender_min_pull_max_wait_ms: u64,
+    /// Whether the batch generator checks that every batch created in the epoch has a distinct
+    /// digest, and logs an error otherwise, e.g. to catch serialization bugs in tests and staging.
+    pub batch_generation_check_digest_collisions: bool,
+    /// Whether the batch generator drops pulled transactions that already expired, instead of
+    /// batching transactions that execution would reject anyway.
+    pub batch_generation_drop_expired_txns: bool,
     /// The maximum number of transactions a single batch received from peers could contain.
     pub receiver_max_batch_txns: usize,
     /// The maximum number of bytes a single batch received from peers could contain.
@@ -85,6 +102,22 @@ pub struct QuorumStoreConfig {
     pub batch_request_retry_limit: usize,
     pub batch_request_retry_interval_ms: usize,
     pub batch_request_rpc_timeout_ms: usize,
+    /// Whether the final batch request retry goes to all signers, instead of batch_request_num_peers.
+    pub batch_request_all_signers_on_last_retry: bool,
+    /// Number of consecutive failed batch requests after which a peer is reported as unresponsive.
+    pub batch_request_peer_failure_threshold: usize,
+    /// How long a peer reported as unresponsive is skipped by batch requests, 0 to never skip it.
+    pub batch_request_peer_cooldown_ms: u64,
+    /// How long a batch request keeps retrying before giving up, 0 to retry until the retry limit.
+    pub batch_request_max_total_duration_ms: u64,
+    /// The maximum total bytes of batches being requested from peers. Further batch prefetches wait
+    /// until enough outstanding ones complete, batches needed for execution are requested right away.
+    pub batch_request_max_outstanding_bytes: usize,
+    /// The maximum number of batch requests sent to peers per second, 0 for no limit.
+    pub batch_request_max_rate_per_sec: usize,
+    /// The maximum number of batch requests that can be sent at once. Must be at least
+    /// batch_request_max_rate_per_sec, if there is a rate limit.
+    pub batch_request_rate_burst: usize,
     /// Duration for expiring locally created batches.
     pub batch_expiry_gap_when_init_usecs: u64,
     /// Duration for expiring remotely created batches. The txns are filtered to prevent dupliation across validators.
@@ -112,10 +145,15 @@ impl Default for QuorumStoreConfig {
             sender_max_batch_txns: DEFEAULT_MAX_BATCH_TXNS,
             // TODO: on next release, remove BATCH_PADDING_BYTES
             sender_max_batch_bytes: 1024 * 1024 - BATCH_PADDING_BYTES,
+            sender_batch_txn_weight_overhead: 0,
             sender_max_num_batches: DEFAULT_MAX_NUM_BATCHES,
             sender_max_total_txns: 1500,
             // TODO: on next release, remove DEFAULT_MAX_NUM_BATCHES * BATCH_PADDING_BYTES
             sender_max_total_bytes: 4 * 1024 * 1024 - DEFAULT_MAX_NUM_BATCHES * BATCH_PADDING_BYTES,
+            sender_min_pull_txns: 0,
+            sender_min_pull_max_wait_ms: 50,
+            batch_generation_check_digest_collisions: false,
+            batch_generation_drop_expired_txns: false,
             receiver_max_batch_txns: 250,
             receiver_max_batch_bytes: 1024 * 1024 + BATCH_PADDING_BYTES,
             receiver_max_num_batches: 20,
@@ -127,6 +165,13 @@ impl Default for QuorumStoreConfig {
             batch_request_retry_limit: 10,
             batch_request_retry_interval_ms: 1000,
             batch_request_rpc_timeout_ms: 5000,
+            batch_request_all_signers_on_last_retry: false,
+            batch_request_peer_failure_threshold: 10,
+            batch_request_peer_cooldown_ms: 0,
+            batch_request_max_total_duration_ms: 0,
+            batch_request_max_outstanding_bytes: 100_000_000,
+            batch_request_max_rate_per_sec: 0,
+            batch_request_rate_burst: 0,
             batch_expiry_gap_when_init_usecs: Duration::from_secs(60).as_micros() as u64,
             remote_batch_expiry_gap_when_init_usecs: Duration::from_millis(500).as_micros() as u64,
             memory_quota: 120_000_000,
@@ -246,6 +291,46 @@ impl QuorumStoreConfig {
         }
         Ok(())
     }
+
+    fn sanitize_sender_batch_nonzero_limits(
+        sanitizer_name: &str,
+        config: &QuorumStoreConfig,
+    ) -> Result<(), Error> {
+        // A zero limit would reject every transaction and silently stop batch generation
+        let sender_limits = [
+            (config.sender_max_batch_txns, "send_txns"),
+            (config.sender_max_batch_bytes, "send_bytes"),
+        ];
+        for (limit, label) in &sender_limits {
+            if *limit == 0 {
+                return Err(Error::ConfigSanitizerFailed(
+                    sanitizer_name.to_owned(),
+                    format!("Failed {}: must be greater than 0", label),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn sanitize_batch_request_rate_limit(
+        sanitizer_name: &str,
+        config: &QuorumStoreConfig,
+    ) -> Result<(), Error> {
+        // The burst is the capacity of the rate limiter, so it can't hold a second of requests
+        // if it's smaller than the rate
+        if config.batch_request_max_rate_per_sec > 0
+            && config.batch_request_rate_burst < config.batch_request_max_rate_per_sec
+        {
+            return Err(Error::ConfigSanitizerFailed(
+                sanitizer_name.to_owned(),
+                format!(
+                    "Failed batch_request_rate_burst: {} < batch_request_max_rate_per_sec {}",
+                    config.batch_request_rate_burst, config.batch_request_max_rate_per_sec
+                ),
+            ));
+        }
+        Ok(())
+    }
 }
 
 impl ConfigSanitizer for QuorumStoreConfig {
@@ -265,6 +350,18 @@ impl ConfigSanitizer for QuorumStoreConfig {
         // Sanitize the batch total limits
         Self::sanitize_batch_total_limits(&sanitizer_name, &node_config.consensus.quorum_store)?;
 
+        // Sanitize the sender batch limits
+        Self::sanitize_sender_batch_nonzero_limits(
+            &sanitizer_name,
+            &node_config.consensus.quorum_store,
+        )?;
+
+        // Sanitize the batch request rate limit
+        Self::sanitize_batch_request_rate_limit(
+            &sanitizer_name,
+            &node_config.consensus.quorum_store,
+        )?;
+
         Ok(())
     }
 }
@@ -462,4 +559,98 @@ mod test {
             QuorumStoreConfig::sanitize(&node_config, NodeType::Validator, None).unwrap_err();
         assert!(matches!(error, Error::ConfigSanitizerFailed(_, _)));
     }
+
+    #[test]
+    fn test_batch_request_rate_burst_below_rate() {
+        // Create a node config with a burst smaller than the rate
+        let node_config = NodeConfig {
+            consensus: ConsensusConfig {
+                quorum_store: QuorumStoreConfig {
+                    batch_request_max_rate_per_sec: 100,
+                    batch_request_rate_burst: 99,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // Sanitize the config and verify that it fails
+        let error =
+            QuorumStoreConfig::sanitize(&node_config, NodeType::Validator, None).unwrap_err();
+        assert!(matches!(error, Error::ConfigSanitizerFailed(_, _)));
+
+        // A burst of at least the rate is valid
+        let node_config = NodeConfig {
+            consensus: ConsensusConfig {
+                quorum_store: QuorumStoreConfig {
+                    batch_request_max_rate_per_sec: 100,
+                    batch_request_rate_burst: 100,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        QuorumStoreConfig::sanitize(&node_config, NodeType::Validator, None).unwrap();
+    }
+
+    #[test]
+    fn test_sender_batch_limits_zero_txns() {
+        // Create a node config with a zero sender txn limit
+        let node_config = NodeConfig {
+            consensus: ConsensusConfig {
+                quorum_store: QuorumStoreConfig {
+                    sender_max_batch_txns: 0,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // Sanitize the config and verify that it fails
+        let error =
+            QuorumStoreConfig::sanitize(&node_config, NodeType::Validator, None).unwrap_err();
+        assert!(matches!(error, Error::ConfigSanitizerFailed(_, _)));
+    }
+
+    #[test]
+    fn test_sender_batch_limits_nonzero() {
+        // Create a node config with the smallest nonzero sender limits
+        let node_config = NodeConfig {
+            consensus: ConsensusConfig {
+                quorum_store: QuorumStoreConfig {
+                    sender_max_batch_txns: 1,
+                    sender_max_batch_bytes: 1,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // Sanitize the config and verify that it succeeds
+        QuorumStoreConfig::sanitize(&node_config, NodeType::Validator, None).unwrap();
+    }
+
+    #[test]
+    fn test_sender_batch_limits_zero_bytes() {
+        // Create a node config with a zero sender byte limit
+        let node_config = NodeConfig {
+            consensus: ConsensusConfig {
+                quorum_store: QuorumStoreConfig {
+                    sender_max_batch_bytes: 0,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // Sanitize the config and verify that it fails
+        let error =
+            QuorumStoreConfig::sanitize(&node_config, NodeType::Validator, None).unwrap_err();
+        assert!(matches!(error, Error::ConfigSanitizerFailed(_, _)));
+    }
 }
@@ -737,6 +737,7 @@ impl<P: OnChainConfigProvider> EpochManager<P> {
                 self.quorum_store_storage.clone(),
                 !consensus_config.is_dag_enabled(),
                 consensus_key,
+                self.aptos_time_service.clone(),
             ))
         } else {
             info!("Building DirectMempool");
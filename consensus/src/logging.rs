@@ -20,6 +20,11 @@ pub struct LogSchema {
 
 #[derive(Serialize)]
 pub enum LogEvent {
+    BatchRequestCancelled,
+    BatchRequestError,
+    BatchRequestExpired,
+    BatchRequestTimeout,
+    BatchRequestUnresponsivePeer,
     BroadcastOrderVote,
     CommitViaBlock,
     CommitViaSync,
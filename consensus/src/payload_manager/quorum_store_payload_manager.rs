@@ -8,7 +8,10 @@ use crate::{
     },
     counters,
     payload_manager::TPayloadManager,
-    quorum_store::{batch_store::BatchReader, quorum_store_coordinator::CoordinatorCommand},
+    quorum_store::{
+        batch_store::{BatchReader, BatchRequestPriority},
+        quorum_store_coordinator::CoordinatorCommand,
+    },
 };
 use aptos_bitvec::BitVec;
 use aptos_consensus_types::{
@@ -89,6 +92,7 @@ impl QuorumStorePayloadManager {
         batches: Vec<(BatchInfo, Vec<PeerId>)>,
         block_timestamp: u64,
         batch_reader: Arc<dyn BatchReader>,
+        priority: BatchRequestPriority,
     ) -> Vec<Shared<Pin<Box<dyn Future<Output = ExecutorResult<Vec<SignedTransaction>>> + Send>>>>
     {
         let mut futures = Vec::new();
@@ -99,7 +103,7 @@ impl QuorumStorePayloadManager {
                 block_timestamp
             );
             if block_timestamp <= batch_info.expiration() {
-                futures.push(batch_reader.get_batch(batch_info, responders.clone()));
+                futures.push(batch_reader.get_batch(batch_info, responders.clone(), priority));
             } else {
                 debug!("QSE: skipped expired batch {}", batch_info.digest());
             }
@@ -112,7 +116,12 @@ impl QuorumStorePayloadManager {
         block_timestamp: u64,
         batch_reader: Arc<dyn BatchReader>,
     ) -> ExecutorResult<Vec<SignedTransaction>> {
-        let futures = Self::request_transactions(batches, block_timestamp, batch_reader);
+        let futures = Self::request_transactions(
+            batches,
+            block_timestamp,
+            batch_reader,
+            BatchRequestPriority::High,
+        );
         let mut all_txns = Vec::new();
         for result in futures::future::join_all(futures).await {
             all_txns.append(&mut result?);
@@ -225,6 +234,7 @@ impl TPayloadManager for QuorumStorePayloadManager {
                         .collect(),
                     timestamp,
                     batch_reader,
+                    BatchRequestPriority::Low,
                 );
             };
 
@@ -250,6 +260,7 @@ impl TPayloadManager for QuorumStorePayloadManager {
                 batches_and_responders,
                 timestamp,
                 batch_reader,
+                BatchRequestPriority::Low,
             );
         }
 
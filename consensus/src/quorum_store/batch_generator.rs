@@ -8,7 +8,7 @@ use crate::{
         counters,
         quorum_store_db::QuorumStoreStorage,
         types::Batch,
-        utils::{MempoolProxy, TimeExpirations},
+        utils::{MempoolProxy, RollingAverage, TimeExpirations},
     },
 };
 use aptos_config::config::QuorumStoreConfig;
@@ -16,9 +16,11 @@ use aptos_consensus_types::{
     common::{TransactionInProgress, TransactionSummary},
     proof_of_store::{BatchId, BatchInfo},
 };
+use aptos_crypto::HashValue;
 use aptos_experimental_runtimes::thread_manager::optimal_min_len;
 use aptos_logger::prelude::*;
 use aptos_mempool::QuorumStoreRequest;
+use aptos_time_service::{TimeService, TimeServiceTrait};
 use aptos_types::{transaction::SignedTransaction, PeerId};
 use futures_channel::mpsc::Sender;
 use rayon::prelude::*;
@@ -57,6 +59,70 @@ impl BatchInProgress {
     }
 }
 
+/// Returns how many of the leading txns fit into a single batch of at most max_txns txns and
+/// max_bytes bytes, stopping at the first txn that doesn't fit.
+pub(crate) fn num_txns_within_limits(
+    txns: &[SignedTransaction],
+    max_txns: usize,
+    max_bytes: u64,
+) -> usize {
+    let mut batch_bytes_remaining = max_bytes;
+    txns.iter()
+        .take(max_txns)
+        .take_while(|txn| {
+            let txn_bytes = txn.txn_bytes_len() as u64;
+            if batch_bytes_remaining.checked_sub(txn_bytes).is_some() {
+                batch_bytes_remaining -= txn_bytes;
+                true
+            } else {
+                false
+            }
+        })
+        .count()
+}
+
+/// Like num_txns_within_limits, but the txns must also fit into max_weight, where each txn
+/// weighs weight_fn, e.g. to account for txns that cost more to execute than their size suggests.
+/// A leading txn that weighs more than max_weight on its own still fills a batch by itself.
+pub(crate) fn num_txns_within_weight_limits(
+    txns: &[SignedTransaction],
+    max_txns: usize,
+    max_bytes: u64,
+    max_weight: u64,
+    weight_fn: impl Fn(&SignedTransaction) -> u64,
+) -> usize {
+    let mut batch_bytes_remaining = max_bytes;
+    let mut batch_weight_remaining = max_weight;
+    txns.iter()
+        .take(max_txns)
+        .enumerate()
+        .take_while(|(index, txn)| {
+            match (
+                batch_bytes_remaining.checked_sub(txn.txn_bytes_len() as u64),
+                batch_weight_remaining.checked_sub(weight_fn(txn)),
+            ) {
+                (Some(bytes_remaining), Some(weight_remaining)) => {
+                    batch_bytes_remaining = bytes_remaining;
+                    batch_weight_remaining = weight_remaining;
+                    true
+                },
+                (Some(bytes_remaining), None) if *index == 0 => {
+                    batch_bytes_remaining = bytes_remaining;
+                    batch_weight_remaining = 0;
+                    true
+                },
+                _ => false,
+            }
+        })
+        .count()
+}
+
+/// Weight of a txn when filling a batch, see num_txns_within_weight_limits.
+type TxnWeightFn = dyn Fn(&SignedTransaction) -> u64 + Send + Sync;
+
+// number of most recent batches the fill ratio is averaged over
+const BATCH_FILL_RATIO_WINDOW: usize = 100;
+
 pub struct BatchGenerator {
     epoch: u64,
     my_peer_id: PeerId,
@@ -72,6 +138,17 @@ pub struct BatchGenerator {
     last_end_batch_time: Instant,
     // quorum store back pressure, get updated from proof manager
     back_pressure: BackPressure,
+    // batch bytes relative to sender_max_batch_bytes, over the last batches
+    bytes_fill_ratio: RollingAverage,
+    // digests of the batches created in the epoch, if collisions are checked
+    created_digests: Option<HashMap<HashValue, BatchId>>,
+    // clock pulled txns are checked for expiration by, if expired txns are dropped
+    time_service: TimeService,
+    // expired txns dropped from pulls, kept in txns_in_progress_sorted until a committed block
+    // is past their expiration and mempool garbage collected them
+    expired_txns: TimeExpirations<TransactionSummary>,
+    // weight batches are filled by, besides their size, if any
+    txn_weight_fn: Option<Box<TxnWeightFn>>,
 }
 
 impl BatchGenerator {
@@ -99,6 +176,16 @@ impl BatchGenerator {
         incremented_batch_id.increment();
         db.save_batch_id(epoch, incremented_batch_id)
             .expect("Could not save to db");
+        // remembers every digest created in the epoch, so it's not meant for production
+        let created_digests = config
+            .batch_generation_check_digest_collisions
+            .then(HashMap::new);
+        let txn_weight_overhead = config.sender_batch_txn_weight_overhead as u64;
+        let txn_weight_fn = (txn_weight_overhead > 0).then(|| -> Box<TxnWeightFn> {
+            Box::new(move |txn: &SignedTransaction| {
+                txn.txn_bytes_len() as u64 + txn_weight_overhead
+            })
+        });
 
         Self {
             epoch,
@@ -117,9 +204,32 @@ impl BatchGenerator {
                 txn_count: false,
                 proof_count: false,
             },
+            bytes_fill_ratio: RollingAverage::new(BATCH_FILL_RATIO_WINDOW),
+            created_digests,
+            time_service: TimeService::real(),
+            expired_txns: TimeExpirations::new(),
+            txn_weight_fn,
         }
     }
 
+    /// Checks pulled txns for expiration by time_service, e.g. a mock one that tests advance
+    /// manually.
+    pub(crate) fn with_time_service(mut self, time_service: TimeService) -> Self {
+        self.time_service = time_service;
+        self
+    }
+
+    /// Fills batches up to sender_max_batch_bytes of weight by weight_fn, as well as of size,
+    /// instead of by the weight from sender_batch_txn_weight_overhead.
+    #[cfg(test)]
+    pub(crate) fn with_txn_weight_fn(
+        mut self,
+        weight_fn: impl Fn(&SignedTransaction) -> u64 + Send + Sync + 'static,
+    ) -> Self {
+        self.txn_weight_fn = Some(Box::new(weight_fn));
+        self
+    }
+
     fn insert_batch(
         &mut self,
         author: PeerId,
@@ -187,14 +297,33 @@ impl BatchGenerator {
         counters::CREATED_BATCHES_COUNT.inc();
         counters::num_txn_per_batch(bucket_start.to_string().as_str(), txns.len());
 
-        Batch::new(
+        let batch = Batch::new(
             batch_id,
             txns,
             self.epoch,
             expiry_time,
             self.my_peer_id,
             bucket_start,
-        )
+        );
+        counters::num_bytes_per_batch(bucket_start.to_string().as_str(), batch.num_bytes());
+        self.bytes_fill_ratio
+            .add(batch.num_bytes() as f64 / self.config.sender_max_batch_bytes as f64);
+        if let Some(average_fill_ratio) = self.bytes_fill_ratio.average() {
+            counters::BATCH_BYTES_FILL_RATIO.set(average_fill_ratio);
+        }
+        if let Some(created_digests) = &mut self.created_digests {
+            if let Some(other_batch_id) = created_digests.insert(*batch.digest(), batch_id) {
+                counters::BATCH_DIGEST_COLLISION_COUNT.inc();
+                error!(
+                    "QS: batch {} has the same digest {} as batch {} in epoch {}",
+                    batch_id,
+                    batch.digest(),
+                    other_batch_id,
+                    self.epoch
+                );
+            }
+        }
+        batch
     }
 
     /// Push num_txns from txns into batches. If num_txns is larger than max size, then multiple
@@ -213,21 +342,18 @@ impl BatchGenerator {
             if *total_batches_remaining == 0 {
                 return;
             }
-            let num_take_txns = std::cmp::min(self.config.sender_max_batch_txns, txns_remaining);
-            let mut batch_bytes_remaining = self.config.sender_max_batch_bytes as u64;
-            let num_batch_txns = txns
-                .iter()
-                .take(num_take_txns)
-                .take_while(|txn| {
-                    let txn_bytes = txn.txn_bytes_len() as u64;
-                    if batch_bytes_remaining.checked_sub(txn_bytes).is_some() {
-                        batch_bytes_remaining -= txn_bytes;
-                        true
-                    } else {
-                        false
-                    }
-                })
-                .count();
+            let max_batch_txns = std::cmp::min(self.config.sender_max_batch_txns, txns_remaining);
+            let max_batch_bytes = self.config.sender_max_batch_bytes as u64;
+            let num_batch_txns = match &self.txn_weight_fn {
+                Some(weight_fn) => num_txns_within_weight_limits(
+                    txns,
+                    max_batch_txns,
+                    max_batch_bytes,
+                    max_batch_bytes,
+                    weight_fn.as_ref(),
+                ),
+                None => num_txns_within_limits(txns, max_batch_txns, max_batch_bytes),
+            };
             if num_batch_txns > 0 {
                 let batch_txns: Vec<_> = txns.drain(0..num_batch_txns).collect();
                 let batch = self.create_new_batch(batch_txns, expiry_time, bucket_start);
@@ -302,12 +428,7 @@ impl BatchGenerator {
         match removed {
             Some(batch_in_progress) => {
                 for txn in batch_in_progress.txns {
-                    if let Entry::Occupied(mut o) = self.txns_in_progress_sorted.entry(txn) {
-                        let info = o.get_mut();
-                        if info.decrement() == 0 {
-                            o.remove();
-                        }
-                    }
+                    self.remove_txn_in_progress(txn);
                 }
                 true
             },
@@ -315,6 +436,30 @@ impl BatchGenerator {
         }
     }
 
+    fn remove_txn_in_progress(&mut self, txn: TransactionSummary) {
+        if let Entry::Occupied(mut o) = self.txns_in_progress_sorted.entry(txn) {
+            let info = o.get_mut();
+            if info.decrement() == 0 {
+                o.remove();
+            }
+        }
+    }
+
+    /// Excludes the dropped expired txns from later pulls, as mempool keeps them until it garbage
+    /// collects them by the timestamp of a committed block.
+    fn exclude_expired_txns(&mut self, txns: Vec<SignedTransaction>) {
+        for txn in txns {
+            let summary =
+                TransactionSummary::new(txn.sender(), txn.replay_protector(), txn.committed_hash());
+            self.txns_in_progress_sorted
+                .entry(summary)
+                .or_insert_with(|| TransactionInProgress::new(txn.gas_unit_price()))
+                .increment();
+            self.expired_txns
+                .add_item(summary, txn.expiration_timestamp_secs() * 1_000_000);
+        }
+    }
+
     #[cfg(test)]
     pub fn remove_batch_in_progress_for_test(&mut self, author: PeerId, batch_id: BatchId) -> bool {
         self.remove_batch_in_progress(author, batch_id)
@@ -332,15 +477,36 @@ impl BatchGenerator {
             self.txns_in_progress_sorted.len()
         );
 
-        let mut pulled_txns = self
-            .mempool_proxy
-            .pull_internal(
-                max_count,
-                self.config.sender_max_total_bytes as u64,
-                self.txns_in_progress_sorted.clone(),
-            )
-            .await
-            .unwrap_or_default();
+        let min_pull_txns = std::cmp::min(self.config.sender_min_pull_txns as u64, max_count);
+        let pull_result = if min_pull_txns > 0 {
+            self.mempool_proxy
+                .pull_internal_min_items(
+                    max_count,
+                    self.config.sender_max_total_bytes as u64,
+                    min_pull_txns,
+                    Duration::from_millis(self.config.sender_min_pull_max_wait_ms),
+                    self.txns_in_progress_sorted.clone(),
+                )
+                .await
+        } else {
+            self.mempool_proxy
+                .pull_internal(
+                    max_count,
+                    self.config.sender_max_total_bytes as u64,
+                    self.txns_in_progress_sorted.clone(),
+                )
+                .await
+        };
+        let mut pulled_txns = pull_result.unwrap_or_default();
+        if self.config.batch_generation_drop_expired_txns {
+            let now_secs = self.time_service.now_unix_time().as_secs();
+            let (unexpired_txns, expired_txns): (Vec<_>, Vec<_>) = pulled_txns
+                .into_iter()
+                .partition(|txn| txn.expiration_timestamp_secs() > now_secs);
+            counters::PULLED_EXPIRED_TXNS_COUNT.inc_by(expired_txns.len() as u64);
+            self.exclude_expired_txns(expired_txns);
+            pulled_txns = unexpired_txns;
+        }
 
         trace!("QS: pulled_txns len: {:?}", pulled_txns.len());
 
@@ -526,6 +692,9 @@ impl BatchGenerator {
                                     );
                                 }
                             }
+                            for txn in self.expired_txns.expire(block_timestamp) {
+                                self.remove_txn_in_progress(txn);
+                            }
                         },
                         BatchGeneratorCommand::ProofExpiration(batch_ids) => {
                             for batch_id in batch_ids {
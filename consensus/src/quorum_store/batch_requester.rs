@@ -2,11 +2,13 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
+    logging::{LogEvent, LogSchema},
     monitor,
     network::QuorumStoreSender,
     quorum_store::{
         counters,
         types::{BatchRequest, BatchResponse, PersistedValue},
+        utils::QuorumStoreRng,
     },
 };
 use aptos_crypto::HashValue;
@@ -14,53 +16,369 @@ use aptos_executor_types::*;
 use aptos_infallible::Mutex;
 use aptos_logger::prelude::*;
 use aptos_types::{transaction::SignedTransaction, validator_verifier::ValidatorVerifier, PeerId};
+use async_trait::async_trait;
 use futures::{stream::FuturesUnordered, StreamExt};
-use rand::Rng;
-use std::{collections::BTreeSet, sync::Arc, time::Duration};
-use tokio::{sync::oneshot, time};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{
+        oneshot::{self, error::TryRecvError},
+        watch,
+    },
+    time::{self, Instant},
+};
+
+/// Token bucket of batch requests, on the tokio clock so that tests can pause it.
+struct RequestRateLimit {
+    burst: usize,
+    refill_interval: Duration,
+    tokens: usize,
+    last_refill: Instant,
+}
+
+impl RequestRateLimit {
+    fn new(max_rate_per_sec: usize, burst: usize) -> Self {
+        let burst = burst.max(1);
+        Self {
+            burst,
+            refill_interval: Duration::from_secs(1) / max_rate_per_sec as u32,
+            tokens: burst,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes a token if there is one, and otherwise returns when the next one is refilled.
+    fn try_acquire(&mut self) -> Result<(), Instant> {
+        let now = Instant::now();
+        let num_refills = (now.duration_since(self.last_refill).as_nanos()
+            / self.refill_interval.as_nanos()) as usize;
+        if num_refills > 0 {
+            self.tokens = self.burst.min(self.tokens.saturating_add(num_refills));
+            self.last_refill = if self.tokens == self.burst {
+                now
+            } else {
+                self.last_refill + self.refill_interval * num_refills as u32
+            };
+        }
+        if self.tokens > 0 {
+            self.tokens -= 1;
+            Ok(())
+        } else {
+            Err(self.last_refill + self.refill_interval)
+        }
+    }
+}
 
 struct BatchRequesterState {
     signers: Arc<Mutex<BTreeSet<PeerId>>>,
+    start_offset: usize,
     next_index: usize,
     num_retries: usize,
     retry_limit: usize,
+    all_signers_on_last_retry: bool,
+    deterministic_peer_order: bool,
+    rng: QuorumStoreRng,
+    num_not_found: usize,
+    num_errors: usize,
+    // every peer the batch was requested from so far
+    requested_peers: BTreeSet<PeerId>,
+    // peers requested in the current retry, including the replacements of failed peers
+    round_peers: HashSet<PeerId>,
+    // peers that responded they don't have the batch
+    not_found_peers: HashSet<PeerId>,
+    // peers skipped until the given time, see BatchRequester::with_peer_cooldown
+    excluded_peers: Arc<Mutex<HashMap<PeerId, Instant>>>,
 }
 
 impl BatchRequesterState {
-    fn new(signers: Arc<Mutex<BTreeSet<PeerId>>>, retry_limit: usize) -> Self {
+    fn new(
+        signers: Arc<Mutex<BTreeSet<PeerId>>>,
+        retry_limit: usize,
+        all_signers_on_last_retry: bool,
+        deterministic_peer_order: bool,
+        rng: QuorumStoreRng,
+        excluded_peers: Arc<Mutex<HashMap<PeerId, Instant>>>,
+    ) -> Self {
         Self {
             signers,
+            start_offset: 0,
             next_index: 0,
             num_retries: 0,
             retry_limit,
+            all_signers_on_last_retry,
+            deterministic_peer_order,
+            rng,
+            num_not_found: 0,
+            num_errors: 0,
+            requested_peers: BTreeSet::new(),
+            round_peers: HashSet::new(),
+            not_found_peers: HashSet::new(),
+            excluded_peers,
         }
     }
 
     fn next_request_peers(&mut self, num_peers: usize) -> Option<Vec<PeerId>> {
+        self.next_request_peers_among(num_peers, false)
+    }
+
+    /// Like next_request_peers, but skips the signers that responded they don't have the batch.
+    /// None if only those signers are left.
+    fn next_request_peers_not_missing_batch(&mut self, num_peers: usize) -> Option<Vec<PeerId>> {
+        self.next_request_peers_among(num_peers, true)
+    }
+
+    fn next_request_peers_among(
+        &mut self,
+        num_peers: usize,
+        skip_not_found_peers: bool,
+    ) -> Option<Vec<PeerId>> {
         let signers = self.signers.lock();
+        let mut signers = self.available_signers(&signers);
+        if skip_not_found_peers {
+            signers.retain(|peer| !self.not_found_peers.contains(peer));
+        }
+        if signers.is_empty() {
+            return None;
+        }
+        let num_peers =
+            if self.all_signers_on_last_retry && self.num_retries + 1 == self.retry_limit {
+                // last resort before giving up, request from all the signers at once
+                signers.len()
+            } else {
+                // requesting from more peers than signers would only request some of them twice
+                num_peers.min(signers.len())
+            };
         if self.num_retries == 0 {
-            let mut rng = rand::thread_rng();
-            // make sure nodes request from the different set of nodes
-            self.next_index = rng.gen::<usize>() % signers.len();
+            // make sure nodes request from the different set of nodes, unless the order is
+            // pinned, in which case the signers are cycled from the smallest PeerId
+            self.start_offset = if self.deterministic_peer_order {
+                0
+            } else {
+                self.rng.gen_range(0, signers.len())
+            };
+            self.next_index = 0;
             counters::SENT_BATCH_REQUEST_COUNT.inc_by(num_peers as u64);
         } else {
             counters::SENT_BATCH_REQUEST_RETRY_COUNT.inc_by(num_peers as u64);
         }
         if self.num_retries < self.retry_limit {
             self.num_retries += 1;
-            let ret = signers
+            let ordered_signers = self.ordered_signers(&signers);
+            let ret: Vec<_> = ordered_signers
                 .iter()
                 .cycle()
                 .skip(self.next_index)
                 .take(num_peers)
                 .cloned()
                 .collect();
-            self.next_index = (self.next_index + num_peers) % signers.len();
+            self.requested_peers.extend(ret.iter().cloned());
+            self.round_peers = ret.iter().cloned().collect();
+            self.next_index = (self.next_index + num_peers) % ordered_signers.len();
             Some(ret)
         } else {
             None
         }
     }
+
+    /// The next signer not requested in the current retry, to replace a failed request. Unlike
+    /// next_request_peers it doesn't use up a retry, so retries are counted per round.
+    fn replacement_peer(&mut self) -> Option<PeerId> {
+        if self.num_retries == 0 {
+            return None;
+        }
+        let signers = self.available_signers(&self.signers.lock());
+        let ordered_signers = self.ordered_signers(&signers);
+        let offset = (0..ordered_signers.len()).find(|offset| {
+            let index = (self.next_index + offset) % ordered_signers.len();
+            !self.round_peers.contains(&ordered_signers[index])
+        })?;
+        let index = (self.next_index + offset) % ordered_signers.len();
+        let peer = ordered_signers[index];
+        self.next_index = (index + 1) % ordered_signers.len();
+        self.round_peers.insert(peer);
+        self.requested_peers.insert(peer);
+        counters::SENT_BATCH_REQUEST_RETRY_COUNT.inc();
+        Some(peer)
+    }
+
+    /// The signers that aren't excluded, or all of them if they all are, so that excluding
+    /// peers never leaves a batch without anyone to request it from.
+    fn available_signers(&self, signers: &BTreeSet<PeerId>) -> BTreeSet<PeerId> {
+        let now = Instant::now();
+        let excluded_peers = self.excluded_peers.lock();
+        let available_signers: BTreeSet<_> = signers
+            .iter()
+            .filter(|peer| {
+                excluded_peers
+                    .get(peer)
+                    .map_or(true, |excluded_until| *excluded_until <= now)
+            })
+            .cloned()
+            .collect();
+        if available_signers.is_empty() {
+            signers.clone()
+        } else {
+            available_signers
+        }
+    }
+
+    /// The signers in request order, cycled in PeerId order starting from start_offset modulo
+    /// their number.
+    fn ordered_signers(&self, signers: &BTreeSet<PeerId>) -> Vec<PeerId> {
+        let mut ordered_signers: Vec<_> = signers.iter().cloned().collect();
+        if !ordered_signers.is_empty() {
+            let mid = self.start_offset % ordered_signers.len();
+            ordered_signers.rotate_left(mid);
+        }
+        ordered_signers
+    }
+
+    /// Why the request failed once the retries are exhausted.
+    fn failure(&self) -> BatchRequestFailure {
+        if self.num_retries == 0 {
+            BatchRequestFailure::NoSigners
+        } else if self.num_not_found > self.num_errors {
+            BatchRequestFailure::NotFound
+        } else {
+            BatchRequestFailure::Unreachable
+        }
+    }
+}
+
+/// Why a batch request failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatchRequestFailure {
+    /// There were no signers to request the batch from.
+    NoSigners,
+    /// The batch expired before it could be fetched.
+    Expired,
+    /// Retries were exhausted, mostly with peers responding that they don't have the batch.
+    NotFound,
+    /// Retries were exhausted, mostly with requests failing or timing out.
+    Unreachable,
+    /// The request took longer than the max total duration, with retries left.
+    DeadlineExceeded,
+    /// The request was cancelled by shutdown.
+    ShuttingDown,
+}
+
+/// Maps a failure at the executor boundary, where only shutdown is told apart from missing data.
+impl From<BatchRequestFailure> for ExecutorError {
+    fn from(failure: BatchRequestFailure) -> Self {
+        match failure {
+            BatchRequestFailure::ShuttingDown => ExecutorError::ShuttingDown,
+            _ => ExecutorError::CouldNotGetData,
+        }
+    }
+}
+
+/// Fetches batches that are missing from the local batch store.
+#[async_trait]
+pub trait BatchRetriever: Send + Sync {
+    /// Fetches the batch with the given digest from the responders, or from subscriber_rx if the
+    /// batch reaches the local batch store first. Returns the payload along with the peer that
+    /// served it, if any.
+    async fn request_batch(
+        &self,
+        digest: HashValue,
+        expiration: u64,
+        responders: Arc<Mutex<BTreeSet<PeerId>>>,
+        subscriber_rx: oneshot::Receiver<PersistedValue>,
+    ) -> Result<(Vec<SignedTransaction>, Option<PeerId>), BatchRequestFailure>;
+
+    /// Fails all the outstanding batch requests with `BatchRequestFailure::ShuttingDown`, as well
+    /// as any request made afterwards.
+    fn shutdown(&self);
+}
+
+/// Snapshot of the outstanding batch requests, e.g. for a liveness probe.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct BatchRequesterHealth {
+    pub(crate) num_pending_requests: usize,
+    // retries used so far, summed over the pending requests
+    pub(crate) total_retries: usize,
+    // pending requests that used all their retries and fail unless an outstanding rpc succeeds
+    pub(crate) num_requests_at_retry_limit: usize,
+    pub(crate) oldest_request_age: Option<Duration>,
+}
+
+impl BatchRequesterHealth {
+    fn new(pending_requests: &HashMap<u64, PendingBatchRequest>, retry_limit: usize) -> Self {
+        Self {
+            num_pending_requests: pending_requests.len(),
+            total_retries: pending_requests
+                .values()
+                .map(|request| request.num_retries)
+                .sum(),
+            num_requests_at_retry_limit: pending_requests
+                .values()
+                .filter(|request| request.num_retries >= retry_limit)
+                .count(),
+            oldest_request_age: pending_requests
+                .values()
+                .map(|request| request.start_time.elapsed())
+                .max(),
+        }
+    }
+
+    /// Exports the snapshot as the batch request health metrics, to detect degrading batch
+    /// retrieval before the requests actually fail.
+    fn update_metrics(&self) {
+        counters::BATCH_REQUEST_OUTSTANDING_COUNT.set(self.num_pending_requests as i64);
+        counters::BATCH_REQUEST_PENDING_RETRIES.set(self.total_retries as i64);
+        counters::BATCH_REQUEST_AT_RETRY_LIMIT_COUNT.set(self.num_requests_at_retry_limit as i64);
+        counters::BATCH_REQUEST_OLDEST_AGE_MS.set(
+            self.oldest_request_age
+                .map_or(0, |age| age.as_millis() as i64),
+        );
+    }
+}
+
+struct PendingBatchRequest {
+    start_time: Instant,
+    num_retries: usize,
+}
+
+/// Keeps a request in the pending requests until dropped, so that a request whose future is
+/// dropped before it completes, e.g. a fetch aborted by cancel_expired_fetches, is removed too.
+struct PendingBatchRequestGuard<'a> {
+    pending_requests: &'a Mutex<HashMap<u64, PendingBatchRequest>>,
+    retry_limit: usize,
+    request_id: u64,
+}
+
+impl<'a> PendingBatchRequestGuard<'a> {
+    fn new(
+        pending_requests: &'a Mutex<HashMap<u64, PendingBatchRequest>>,
+        retry_limit: usize,
+        request_id: u64,
+    ) -> Self {
+        let mut requests = pending_requests.lock();
+        requests.insert(request_id, PendingBatchRequest {
+            start_time: Instant::now(),
+            num_retries: 0,
+        });
+        BatchRequesterHealth::new(&requests, retry_limit).update_metrics();
+        Self {
+            pending_requests,
+            retry_limit,
+            request_id,
+        }
+    }
+}
+
+impl Drop for PendingBatchRequestGuard<'_> {
+    fn drop(&mut self) {
+        let mut requests = self.pending_requests.lock();
+        requests.remove(&self.request_id);
+        BatchRequesterHealth::new(&requests, self.retry_limit).update_metrics();
+    }
 }
 
 pub(crate) struct BatchRequester<T> {
@@ -70,8 +388,23 @@ pub(crate) struct BatchRequester<T> {
     retry_limit: usize,
     retry_interval_ms: usize,
     rpc_timeout_ms: usize,
+    all_signers_on_last_retry: bool,
+    peer_failure_threshold: usize,
     network_sender: T,
     validator_verifier: Arc<ValidatorVerifier>,
+    shutdown_tx: watch::Sender<bool>,
+    // consecutive failed requests per peer, reset when the peer serves a batch
+    peer_failures: Mutex<HashMap<PeerId, usize>>,
+    peer_cooldown: Option<Duration>,
+    excluded_peers: Arc<Mutex<HashMap<PeerId, Instant>>>,
+    deterministic_peer_order: bool,
+    rng: QuorumStoreRng,
+    request_rate_limit: Option<Mutex<RequestRateLimit>>,
+    max_batch_bytes: u64,
+    max_total_duration: Option<Duration>,
+    // keyed by request id, as the same batch can be requested more than once at a time
+    pending_requests: Mutex<HashMap<u64, PendingBatchRequest>>,
+    next_request_id: AtomicU64,
 }
 
 impl<T: QuorumStoreSender + Sync + 'static> BatchRequester<T> {
@@ -85,6 +418,7 @@ impl<T: QuorumStoreSender + Sync + 'static> BatchRequester<T> {
         network_sender: T,
         validator_verifier: Arc<ValidatorVerifier>,
     ) -> Self {
+        let (shutdown_tx, _) = watch::channel(false);
         Self {
             epoch,
             my_peer_id,
@@ -92,86 +426,418 @@ impl<T: QuorumStoreSender + Sync + 'static> BatchRequester<T> {
             retry_limit,
             retry_interval_ms,
             rpc_timeout_ms,
+            all_signers_on_last_retry: false,
+            peer_failure_threshold: usize::MAX,
             network_sender,
             validator_verifier,
+            shutdown_tx,
+            peer_failures: Mutex::new(HashMap::new()),
+            peer_cooldown: None,
+            excluded_peers: Arc::new(Mutex::new(HashMap::new())),
+            deterministic_peer_order: false,
+            rng: QuorumStoreRng::new(),
+            request_rate_limit: None,
+            max_batch_bytes: u64::MAX,
+            max_total_duration: None,
+            pending_requests: Mutex::new(HashMap::new()),
+            next_request_id: AtomicU64::new(0),
+        }
+    }
+
+    /// On the last retry, requests the batch from all the signers at once instead of
+    /// request_num_peers of them, as a last resort before giving up.
+    pub(crate) fn with_all_signers_on_last_retry(
+        mut self,
+        all_signers_on_last_retry: bool,
+    ) -> Self {
+        self.all_signers_on_last_retry = all_signers_on_last_retry;
+        self
+    }
+
+    /// Logs and counts a peer as unresponsive once it failed peer_failure_threshold consecutive
+    /// requests. Without a threshold no peer is ever reported.
+    pub(crate) fn with_peer_failure_threshold(mut self, peer_failure_threshold: usize) -> Self {
+        self.peer_failure_threshold = peer_failure_threshold;
+        self
+    }
+
+    /// Sends at most max_rate_per_sec batch requests per second, and at most burst requests at
+    /// once, where burst is at least max_rate_per_sec. Requests beyond that wait for the limit to
+    /// refill. A rate of 0 means no limit.
+    pub(crate) fn with_request_rate_limit(mut self, max_rate_per_sec: usize, burst: usize) -> Self {
+        if max_rate_per_sec > 0 {
+            self.request_rate_limit =
+                Some(Mutex::new(RequestRateLimit::new(max_rate_per_sec, burst)));
+        }
+        self
+    }
+
+    /// Batches served with more than max_batch_bytes bytes of transactions are dropped, as if
+    /// the peer didn't respond, and the batch is requested again on the next retry.
+    pub(crate) fn with_max_batch_bytes(mut self, max_batch_bytes: u64) -> Self {
+        self.max_batch_bytes = max_batch_bytes;
+        self
+    }
+
+    /// Fails requests that are still retrying max_total_duration after they started, even with
+    /// retries left, e.g. to bound how long a batch request can hold up execution. A zero
+    /// duration means no bound other than the retry limit.
+    pub(crate) fn with_max_total_duration(mut self, max_total_duration: Duration) -> Self {
+        if !max_total_duration.is_zero() {
+            self.max_total_duration = Some(max_total_duration);
+        }
+        self
+    }
+
+    /// Waits until the request rate limit, if any, allows sending another request.
+    async fn wait_for_request_rate_limit(&self) {
+        let Some(request_rate_limit) = &self.request_rate_limit else {
+            return;
+        };
+        let mut throttled = false;
+        loop {
+            let result = request_rate_limit.lock().try_acquire();
+            match result {
+                Ok(()) => return,
+                Err(refill_time) => {
+                    if !throttled {
+                        throttled = true;
+                        counters::SENT_BATCH_REQUEST_THROTTLED_COUNT.inc();
+                    }
+                    time::sleep_until(refill_time).await;
+                },
+            }
+        }
+    }
+
+    /// Requests cycle through the signers in PeerId order starting from the smallest, instead of
+    /// from a random signer, so the sequence of requested peers is reproducible.
+    #[cfg(test)]
+    pub(crate) fn with_deterministic_peer_order(mut self) -> Self {
+        self.deterministic_peer_order = true;
+        self
+    }
+
+    /// Picks the signers to request from first with rng, e.g. one seeded in tests.
+    #[cfg(test)]
+    pub(crate) fn with_rng(mut self, rng: QuorumStoreRng) -> Self {
+        self.rng = rng;
+        self
+    }
+
+    /// Stops requesting batches from a peer for cooldown once it failed peer_failure_threshold
+    /// consecutive requests. After the cooldown the peer is requested again, and is excluded
+    /// again on its next failure, until it serves a batch. A zero cooldown means peers are never
+    /// excluded.
+    pub(crate) fn with_peer_cooldown(mut self, cooldown: Duration) -> Self {
+        if !cooldown.is_zero() {
+            self.peer_cooldown = Some(cooldown);
+        }
+        self
+    }
+
+    /// Peers that failed at least peer_failure_threshold consecutive batch requests.
+    #[cfg(test)]
+    pub(crate) fn unresponsive_peers(&self) -> Vec<PeerId> {
+        self.peer_failures
+            .lock()
+            .iter()
+            .filter(|(_, num_failures)| **num_failures >= self.peer_failure_threshold)
+            .map(|(peer, _)| *peer)
+            .collect()
+    }
+
+    /// Summarizes the pending batch requests, as exported by the batch request health metrics.
+    #[cfg(test)]
+    pub(crate) fn health(&self) -> BatchRequesterHealth {
+        BatchRequesterHealth::new(&self.pending_requests.lock(), self.retry_limit)
+    }
+
+    fn record_retries(&self, request_id: u64, num_retries: usize) {
+        let mut pending_requests = self.pending_requests.lock();
+        if let Some(request) = pending_requests.get_mut(&request_id) {
+            if request.num_retries != num_retries {
+                request.num_retries = num_retries;
+                BatchRequesterHealth::new(&pending_requests, self.retry_limit).update_metrics();
+            }
         }
     }
 
-    pub(crate) async fn request_batch(
+    fn record_peer_failure(&self, peer: PeerId) {
+        let mut peer_failures = self.peer_failures.lock();
+        let num_failures = peer_failures.entry(peer).or_insert(0);
+        *num_failures += 1;
+        if *num_failures == self.peer_failure_threshold {
+            counters::BATCH_REQUEST_UNRESPONSIVE_PEER_COUNT.inc();
+            warn!(
+                LogSchema::new(LogEvent::BatchRequestUnresponsivePeer)
+                    .remote_peer(peer)
+                    .epoch(self.epoch),
+                num_failures = *num_failures,
+                "QS: peer failed to serve consecutive batch requests"
+            );
+        }
+        if let Some(cooldown) = self.peer_cooldown {
+            if *num_failures >= self.peer_failure_threshold {
+                let now = Instant::now();
+                let mut excluded_peers = self.excluded_peers.lock();
+                let excluded_until = excluded_peers.entry(peer).or_insert(now);
+                // a peer that fails while still excluded, e.g. on a request sent before, isn't
+                // excluded for longer
+                if *excluded_until <= now {
+                    *excluded_until = now + cooldown;
+                    counters::BATCH_REQUEST_EXCLUDED_PEER_COUNT.inc();
+                }
+            }
+        }
+    }
+
+    async fn request_batch_inner(
         &self,
+        request_id: u64,
         digest: HashValue,
         expiration: u64,
-        responders: Arc<Mutex<BTreeSet<PeerId>>>,
+        request_state: &mut BatchRequesterState,
         mut subscriber_rx: oneshot::Receiver<PersistedValue>,
-    ) -> ExecutorResult<Vec<SignedTransaction>> {
+    ) -> Result<(Vec<SignedTransaction>, Option<PeerId>), BatchRequestFailure> {
         let validator_verifier = self.validator_verifier.clone();
-        let mut request_state = BatchRequesterState::new(responders, self.retry_limit);
-        let network_sender = self.network_sender.clone();
         let request_num_peers = self.request_num_peers;
         let my_peer_id = self.my_peer_id;
         let epoch = self.epoch;
         let retry_interval = Duration::from_millis(self.retry_interval_ms as u64);
         let rpc_timeout = Duration::from_millis(self.rpc_timeout_ms as u64);
+        let mut shutdown_rx = self.shutdown_tx.subscribe();
+        if *shutdown_rx.borrow() {
+            return Err(BatchRequestFailure::ShuttingDown);
+        }
+        // the batch may have been persisted locally since the caller subscribed. A closed
+        // subscription can't be polled again after try_recv, so it's left out of the select below
+        let mut subscriber_closed = false;
+        match subscriber_rx.try_recv() {
+            Ok(persisted_value) => {
+                counters::RECEIVED_BATCH_FROM_SUBSCRIPTION_COUNT.inc();
+                let (_, maybe_payload) = persisted_value.unpack();
+                return Ok((maybe_payload.expect("persisted value must exist"), None));
+            },
+            Err(TryRecvError::Closed) => subscriber_closed = true,
+            Err(TryRecvError::Empty) => {},
+        }
 
         monitor!("batch_request", {
             let mut interval = time::interval(retry_interval);
+            let deadline = time::sleep(self.max_total_duration.unwrap_or_default());
+            tokio::pin!(deadline);
             let mut futures = FuturesUnordered::new();
             let request = BatchRequest::new(my_peer_id, epoch, digest);
+            let request_peer = |peer: PeerId| {
+                let network_sender = self.network_sender.clone();
+                let request = request.clone();
+                async move {
+                    self.wait_for_request_rate_limit().await;
+                    let response = network_sender
+                        .request_batch(request, peer, rpc_timeout)
+                        .await;
+                    (peer, response)
+                }
+            };
             loop {
+                self.record_retries(request_id, request_state.num_retries);
                 tokio::select! {
                     _ = interval.tick() => {
                         // send batch request to a set of peers of size request_num_peers
                         if let Some(request_peers) = request_state.next_request_peers(request_num_peers) {
                             for peer in request_peers {
-                                futures.push(network_sender.request_batch(request.clone(), peer, rpc_timeout));
+                                futures.push(request_peer(peer));
                             }
                         } else if futures.is_empty() {
                             // end the loop when the futures are drained
                             break;
                         }
                     },
-                    Some(response) = futures.next() => {
+                    Some((peer, response)) = futures.next() => {
                         match response {
                             Ok(BatchResponse::Batch(batch)) => {
                                 counters::RECEIVED_BATCH_RESPONSE_COUNT.inc();
-                                let payload = batch.into_transactions();
-                                return Ok(payload);
+                                // the digest only covers the payload, so the same txns batched in
+                                // another epoch match it too
+                                if batch.epoch() != epoch {
+                                    counters::RECEIVED_BATCH_RESPONSE_WRONG_EPOCH_COUNT.inc();
+                                    request_state.num_errors += 1;
+                                    self.record_peer_failure(peer);
+                                    warn!(
+                                        LogSchema::new(LogEvent::BatchRequestError)
+                                            .remote_peer(peer)
+                                            .epoch(epoch),
+                                        digest = digest,
+                                        batch_epoch = batch.epoch(),
+                                        "QS: served batch is from a different epoch"
+                                    );
+                                    continue;
+                                }
+                                // don't trust the declared size, count the served transactions
+                                let num_bytes: u64 = batch
+                                    .txns()
+                                    .iter()
+                                    .map(|txn| txn.txn_bytes_len() as u64)
+                                    .sum();
+                                if num_bytes <= self.max_batch_bytes {
+                                    self.peer_failures.lock().remove(&peer);
+                                    self.excluded_peers.lock().remove(&peer);
+                                    let payload = batch.into_transactions();
+                                    return Ok((payload, Some(peer)));
+                                }
+                                counters::RECEIVED_BATCH_RESPONSE_TOO_LARGE_COUNT.inc();
+                                request_state.num_errors += 1;
+                                self.record_peer_failure(peer);
+                                warn!(
+                                    LogSchema::new(LogEvent::BatchRequestError)
+                                        .remote_peer(peer)
+                                        .epoch(epoch),
+                                    digest = digest,
+                                    num_bytes = num_bytes,
+                                    max_batch_bytes = self.max_batch_bytes,
+                                    "QS: served batch exceeds max batch bytes"
+                                );
                             }
                             // Short-circuit if the chain has moved beyond expiration
                             Ok(BatchResponse::NotFound(ledger_info)) => {
                                 counters::RECEIVED_BATCH_NOT_FOUND_COUNT.inc();
+                                request_state.num_not_found += 1;
                                 if ledger_info.commit_info().epoch() == epoch
                                     && ledger_info.commit_info().timestamp_usecs() > expiration
                                     && ledger_info.verify_signatures(&validator_verifier).is_ok()
                                 {
                                     counters::RECEIVED_BATCH_EXPIRED_COUNT.inc();
-                                    debug!("QS: batch request expired, digest:{}", digest);
-                                    return Err(ExecutorError::CouldNotGetData);
+                                    debug!(
+                                        LogSchema::new(LogEvent::BatchRequestExpired)
+                                            .remote_peer(peer)
+                                            .epoch(epoch),
+                                        digest = digest,
+                                        retry = request_state.num_retries,
+                                    );
+                                    return Err(BatchRequestFailure::Expired);
+                                }
+                                request_state.not_found_peers.insert(peer);
+                                // once all the outstanding requests missed the batch, send the
+                                // next retry right away to the signers that didn't miss it yet,
+                                // instead of waiting for the retry interval. If all the signers
+                                // missed it, they may only get it later, so wait for the interval
+                                if futures.is_empty() {
+                                    if let Some(request_peers) = request_state.next_request_peers_not_missing_batch(request_num_peers) {
+                                        for peer in request_peers {
+                                            futures.push(request_peer(peer));
+                                        }
+                                        interval.reset();
+                                    }
                                 }
                             }
                             Err(e) => {
                                 counters::RECEIVED_BATCH_RESPONSE_ERROR_COUNT.inc();
-                                debug!("QS: batch request error, digest:{}, error:{:?}", digest, e);
+                                request_state.num_errors += 1;
+                                self.record_peer_failure(peer);
+                                debug!(
+                                    LogSchema::new(LogEvent::BatchRequestError)
+                                        .remote_peer(peer)
+                                        .epoch(epoch),
+                                    digest = digest,
+                                    retry = request_state.num_retries,
+                                    error = ?e,
+                                );
+                                // replace the failed request right away instead of waiting for the next retry
+                                // interval, without using up a retry. Once every signer failed this round,
+                                // the next one waits for the retry interval, to back off from peers that
+                                // fail fast, e.g. when they aren't connected
+                                if let Some(peer) = request_state.replacement_peer() {
+                                    futures.push(request_peer(peer));
+                                }
                             }
                         }
                     },
-                    result = &mut subscriber_rx => {
+                    Ok(()) = shutdown_rx.changed() => {
+                        debug!(
+                            LogSchema::new(LogEvent::BatchRequestCancelled).epoch(epoch),
+                            digest = digest,
+                            retry = request_state.num_retries,
+                        );
+                        return Err(BatchRequestFailure::ShuttingDown);
+                    },
+                    _ = &mut deadline, if self.max_total_duration.is_some() => {
+                        counters::BATCH_REQUEST_DEADLINE_EXCEEDED_COUNT.inc();
+                        debug!(
+                            LogSchema::new(LogEvent::BatchRequestTimeout).epoch(epoch),
+                            digest = digest,
+                            retry = request_state.num_retries,
+                            requested_peers = ?request_state.requested_peers,
+                            "QS: batch request exceeded the max total duration"
+                        );
+                        return Err(BatchRequestFailure::DeadlineExceeded);
+                    },
+                    result = &mut subscriber_rx, if !subscriber_closed => {
                         match result {
                             Ok(persisted_value) => {
                                 counters::RECEIVED_BATCH_FROM_SUBSCRIPTION_COUNT.inc();
                                 let (_, maybe_payload) = persisted_value.unpack();
-                                return Ok(maybe_payload.expect("persisted value must exist"));
+                                return Ok((maybe_payload.expect("persisted value must exist"), None));
                             }
                             Err(err) => {
-                                debug!("channel closed: {}", err);
+                                // a closed subscription stays ready, stop polling it
+                                subscriber_closed = true;
+                                debug!(
+                                    LogSchema::new(LogEvent::BatchRequestError).epoch(epoch),
+                                    digest = digest,
+                                    error = ?err,
+                                    "QS: batch store subscription closed"
+                                );
                             }
                         };
                     },
                 }
             }
             counters::RECEIVED_BATCH_REQUEST_TIMEOUT_COUNT.inc();
-            debug!("QS: batch request timed out, digest:{}", digest);
-            Err(ExecutorError::CouldNotGetData)
+            let failure = request_state.failure();
+            debug!(
+                LogSchema::new(LogEvent::BatchRequestTimeout).epoch(epoch),
+                digest = digest,
+                retry = request_state.num_retries,
+                requested_peers = ?request_state.requested_peers,
+                failure = ?failure,
+            );
+            Err(failure)
         })
     }
 }
+
+#[async_trait]
+impl<T: QuorumStoreSender + Sync + 'static> BatchRetriever for BatchRequester<T> {
+    async fn request_batch(
+        &self,
+        digest: HashValue,
+        expiration: u64,
+        responders: Arc<Mutex<BTreeSet<PeerId>>>,
+        subscriber_rx: oneshot::Receiver<PersistedValue>,
+    ) -> Result<(Vec<SignedTransaction>, Option<PeerId>), BatchRequestFailure> {
+        let request_id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let _pending_request =
+            PendingBatchRequestGuard::new(&self.pending_requests, self.retry_limit, request_id);
+        let mut request_state = BatchRequesterState::new(
+            responders,
+            self.retry_limit,
+            self.all_signers_on_last_retry,
+            self.deterministic_peer_order,
+            self.rng.clone(),
+            self.excluded_peers.clone(),
+        );
+        self.request_batch_inner(
+            request_id,
+            digest,
+            expiration,
+            &mut request_state,
+            subscriber_rx,
+        )
+        .await
+    }
+
+    fn shutdown(&self) {
+        self.shutdown_tx.send_replace(true);
+    }
+}
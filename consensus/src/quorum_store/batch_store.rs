@@ -1,15 +1,12 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{
-    network::QuorumStoreSender,
-    quorum_store::{
-        batch_requester::BatchRequester,
-        counters,
-        quorum_store_db::QuorumStoreStorage,
-        types::{PersistedValue, StorageMode},
-        utils::TimeExpirations,
-    },
+use crate::quorum_store::{
+    batch_requester::BatchRetriever,
+    counters,
+    quorum_store_db::QuorumStoreStorage,
+    types::{PersistedValue, StorageMode},
+    utils::TimeExpirations,
 };
 use anyhow::bail;
 use aptos_consensus_types::proof_of_store::{BatchInfo, SignedBatchInfo};
@@ -23,7 +20,10 @@ use dashmap::{
     DashMap,
 };
 use fail::fail_point;
-use futures::{future::Shared, FutureExt};
+use futures::{
+    future::{abortable, ready, AbortHandle, Shared},
+    FutureExt,
+};
 use once_cell::sync::OnceCell;
 use std::{
     collections::{BTreeSet, HashMap},
@@ -35,7 +35,7 @@ use std::{
     },
     time::Duration,
 };
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, Notify, Semaphore};
 
 // Pub(crate) for testing only.
 pub(crate) struct QuotaManager {
@@ -456,6 +456,14 @@ impl BatchStore {
         }
     }
 
+    /// The batch if its payload is in memory, without falling back to the db.
+    fn get_batch_from_memory(&self, digest: &HashValue) -> Option<PersistedValue> {
+        self.db_cache
+            .get(digest)
+            .filter(|value| value.payload_storage_mode() != StorageMode::PersistedOnly)
+            .map(|value| value.clone())
+    }
+
     /// This calls lets the caller subscribe to a batch being added to the batch store.
     /// This can be useful in cases where there are multiple flows to add a batch (like
     /// direct from author batch / batch requester fetch) to the batch store and either
@@ -495,6 +503,14 @@ impl BatchWriter for BatchStore {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatchRequestPriority {
+    /// The batch is needed to execute a block.
+    High,
+    /// The batch is prefetched ahead of execution.
+    Low,
+}
+
 pub trait BatchReader: Send + Sync {
     /// Check if the batch corresponding to the digest exists, return the batch author if true
     fn exists(&self, digest: &HashValue) -> Option<PeerId>;
@@ -503,28 +519,46 @@ pub trait BatchReader: Send + Sync {
         &self,
         batch_info: BatchInfo,
         signers: Vec<PeerId>,
+        priority: BatchRequestPriority,
     ) -> Shared<Pin<Box<dyn Future<Output = ExecutorResult<Vec<SignedTransaction>>> + Send>>>;
 
     fn update_certified_timestamp(&self, certified_time: u64);
+
+    /// Fails all the in-flight and future batch fetches.
+    fn shutdown(&self);
 }
 
 struct BatchFetchUnit {
+    batch_info: BatchInfo,
     responders: Arc<Mutex<BTreeSet<PeerId>>>,
     fut: Shared<Pin<Box<dyn Future<Output = ExecutorResult<Vec<SignedTransaction>>> + Send>>>,
+    abort_handle: AbortHandle,
+    // notified when a high priority request joins a low priority fetch
+    prioritized: Arc<Notify>,
 }
 
-pub struct BatchReaderImpl<T> {
+pub struct BatchReaderImpl<R> {
     batch_store: Arc<BatchStore>,
-    batch_requester: Arc<BatchRequester<T>>,
+    batch_retriever: Arc<R>,
     inflight_fetch_requests: Arc<Mutex<HashMap<HashValue, BatchFetchUnit>>>,
+    max_outstanding_bytes: u64,
+    // bytes of the batches being requested from peers, requests wait for their batch to fit in
+    outstanding_bytes_budget: Arc<Semaphore>,
 }
 
-impl<T: QuorumStoreSender + Clone + Send + Sync + 'static> BatchReaderImpl<T> {
-    pub(crate) fn new(batch_store: Arc<BatchStore>, batch_requester: BatchRequester<T>) -> Self {
+impl<R: BatchRetriever + 'static> BatchReaderImpl<R> {
+    pub(crate) fn new(
+        batch_store: Arc<BatchStore>,
+        batch_retriever: R,
+        max_outstanding_bytes: u64,
+    ) -> Self {
+        let max_outstanding_bytes = max_outstanding_bytes.min(u32::MAX as u64);
         Self {
             batch_store,
-            batch_requester: Arc::new(batch_requester),
+            batch_retriever: Arc::new(batch_retriever),
             inflight_fetch_requests: Arc::new(Mutex::new(HashMap::new())),
+            max_outstanding_bytes,
+            outstanding_bytes_budget: Arc::new(Semaphore::new(max_outstanding_bytes as usize)),
         }
     }
 
@@ -532,7 +566,15 @@ impl<T: QuorumStoreSender + Clone + Send + Sync + 'static> BatchReaderImpl<T> {
         &self,
         batch_info: BatchInfo,
         responders: Vec<PeerId>,
+        priority: BatchRequestPriority,
     ) -> Shared<Pin<Box<dyn Future<Output = ExecutorResult<Vec<SignedTransaction>>> + Send>>> {
+        // serve batches in memory right away, without spawning a fetch. Batches only in the db
+        // are read by the fetch, so that the caller doesn't block on the db
+        if let Some(mut value) = self.batch_store.get_batch_from_memory(batch_info.digest()) {
+            let payload = value.take_payload().expect("Must have payload");
+            return ready(Ok(payload)).boxed().shared();
+        }
+
         let mut responders = responders.into_iter().collect();
 
         self.inflight_fetch_requests
@@ -540,8 +582,12 @@ impl<T: QuorumStoreSender + Clone + Send + Sync + 'static> BatchReaderImpl<T> {
             .entry(*batch_info.digest())
             .and_modify(|fetch_unit| {
                 fetch_unit.responders.lock().append(&mut responders);
+                if priority == BatchRequestPriority::High {
+                    fetch_unit.prioritized.notify_one();
+                }
             })
             .or_insert_with(|| {
+                let fetch_batch_info = batch_info.clone();
                 let responders = Arc::new(Mutex::new(responders));
                 let responders_clone = responders.clone();
 
@@ -549,9 +595,17 @@ impl<T: QuorumStoreSender + Clone + Send + Sync + 'static> BatchReaderImpl<T> {
 
                 let inflight_requests_clone = self.inflight_fetch_requests.clone();
                 let batch_store = self.batch_store.clone();
-                let requester = self.batch_requester.clone();
+                let retriever = self.batch_retriever.clone();
+                let outstanding_bytes_budget = self.outstanding_bytes_budget.clone();
+                // a batch larger than the whole budget is requested once nothing else is
+                let budget_bytes = batch_info.num_bytes().min(self.max_outstanding_bytes) as u32;
+                let prioritized = Arc::new(Notify::new());
+                let prioritized_clone = prioritized.clone();
+                if priority == BatchRequestPriority::Low && self.is_saturated() {
+                    counters::BATCH_REQUEST_BUDGET_SATURATED_COUNT.inc();
+                }
 
-                let fut = async move {
+                let (fut, abort_handle) = abortable(async move {
                     let batch_digest = *batch_info.digest();
                     defer!({
                         inflight_requests_clone.lock().remove(&batch_digest);
@@ -561,7 +615,25 @@ impl<T: QuorumStoreSender + Clone + Send + Sync + 'static> BatchReaderImpl<T> {
                     } else {
                         // Quorum store metrics
                         counters::MISSED_BATCHES_COUNT.inc();
-                        let payload = requester
+                        // high priority requests don't wait for the outstanding bytes budget
+                        let _budget_permit = match priority {
+                            BatchRequestPriority::High => None,
+                            BatchRequestPriority::Low => {
+                                let acquire_budget =
+                                    outstanding_bytes_budget.acquire_many_owned(budget_bytes);
+                                tokio::select! {
+                                    permit = acquire_budget => {
+                                        Some(permit.expect("Budget is never closed"))
+                                    },
+                                    _ = prioritized_clone.notified() => None,
+                                }
+                            },
+                        };
+                        counters::BATCH_REQUEST_OUTSTANDING_BYTES.add(budget_bytes as i64);
+                        defer!({
+                            counters::BATCH_REQUEST_OUTSTANDING_BYTES.sub(budget_bytes as i64);
+                        });
+                        let (payload, _) = retriever
                             .request_batch(
                                 batch_digest,
                                 batch_info.expiration(),
@@ -573,23 +645,65 @@ impl<T: QuorumStoreSender + Clone + Send + Sync + 'static> BatchReaderImpl<T> {
                             .persist(vec![PersistedValue::new(batch_info, Some(payload.clone()))]);
                         Ok(payload)
                     }
-                }
-                .boxed()
-                .shared();
+                });
+                let fut = fut
+                    .map(|result| match result {
+                        Ok(result) => result,
+                        // cancelled by cancel_expired_fetches
+                        Err(_) => Err(ExecutorError::CouldNotGetData),
+                    })
+                    .boxed()
+                    .shared();
 
                 tokio::spawn(fut.clone());
 
                 BatchFetchUnit {
+                    batch_info: fetch_batch_info,
                     responders: responders_clone,
                     fut,
+                    abort_handle,
+                    prioritized,
                 }
             })
             .fut
             .clone()
     }
+
+    /// Cancels the fetches of batches that the batch store would already have expired at
+    /// certified_time, failing them with `ExecutorError::CouldNotGetData`.
+    fn cancel_expired_fetches(&self, certified_time: u64) {
+        let expiration_time =
+            certified_time.saturating_sub(self.batch_store.expiration_buffer_usecs);
+        for fetch_unit in self.inflight_fetch_requests.lock().values() {
+            if fetch_unit.batch_info.expiration() <= expiration_time {
+                fetch_unit.abort_handle.abort();
+            }
+        }
+    }
+
+    /// Bytes left in the outstanding bytes budget for low priority fetches.
+    pub(crate) fn capacity_remaining(&self) -> u64 {
+        self.outstanding_bytes_budget.available_permits() as u64
+    }
+
+    /// Whether the outstanding bytes budget is used up, so that low priority fetches wait for
+    /// earlier ones to complete.
+    pub(crate) fn is_saturated(&self) -> bool {
+        self.capacity_remaining() == 0
+    }
+
+    /// Total size of the batches being fetched, as declared by their batch infos.
+    #[cfg(test)]
+    pub(crate) fn in_flight_bytes(&self) -> u64 {
+        self.inflight_fetch_requests
+            .lock()
+            .values()
+            .map(|fetch_unit| fetch_unit.batch_info.num_bytes())
+            .sum()
+    }
 }
 
-impl<T: QuorumStoreSender + Clone + Send + Sync + 'static> BatchReader for BatchReaderImpl<T> {
+impl<R: BatchRetriever + 'static> BatchReader for BatchReaderImpl<R> {
     fn exists(&self, digest: &HashValue) -> Option<PeerId> {
         self.batch_store
             .get_batch_from_local(digest)
@@ -601,12 +715,18 @@ impl<T: QuorumStoreSender + Clone + Send + Sync + 'static> BatchReader for Batch
         &self,
         batch_info: BatchInfo,
         responders: Vec<PeerId>,
+        priority: BatchRequestPriority,
     ) -> Shared<Pin<Box<dyn Future<Output = ExecutorResult<Vec<SignedTransaction>>> + Send>>> {
-        self.get_or_fetch_batch(batch_info, responders)
+        self.get_or_fetch_batch(batch_info, responders, priority)
     }
 
     fn update_certified_timestamp(&self, certified_time: u64) {
         self.batch_store.update_certified_timestamp(certified_time);
+        self.cancel_expired_fetches(certified_time);
+    }
+
+    fn shutdown(&self) {
+        self.batch_retriever.shutdown();
     }
 }
 
@@ -4,9 +4,9 @@
 #![allow(clippy::unwrap_used)]
 
 use aptos_metrics_core::{
-    exponential_buckets, op_counters::DurationHistogram, register_avg_counter, register_histogram,
-    register_histogram_vec, register_int_counter, register_int_counter_vec, Histogram,
-    HistogramVec, IntCounter, IntCounterVec,
+    exponential_buckets, op_counters::DurationHistogram, register_avg_counter, register_gauge,
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, Gauge, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge,
 };
 use once_cell::sync::Lazy;
 use std::time::Duration;
@@ -176,6 +176,32 @@ pub fn num_txn_per_batch(bucket_start: &str, num: usize) {
         .observe(num as f64)
 }
 
+/// Histogram for the number of bytes per batch.
+static NUM_BYTES_PER_BATCH: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "quorum_store_num_bytes_per_batch",
+        "Histogram for the number of bytes per batch.",
+        &["bucket"],
+        BYTE_BUCKETS.clone(),
+    )
+    .unwrap()
+});
+
+pub fn num_bytes_per_batch(bucket_start: &str, num: u64) {
+    NUM_BYTES_PER_BATCH
+        .with_label_values(&[bucket_start])
+        .observe(num as f64)
+}
+
+/// Average bytes of the recently created batches, relative to the max batch bytes.
+pub static BATCH_BYTES_FILL_RATIO: Lazy<Gauge> = Lazy::new(|| {
+    register_gauge!(
+        "quorum_store_batch_bytes_fill_ratio",
+        "Average bytes of the recently created batches, relative to the max batch bytes."
+    )
+    .unwrap()
+});
+
 /// Histogram for the number of transactions per block when pulled for consensus.
 pub static BLOCK_SIZE_WHEN_PULL: Lazy<Histogram> = Lazy::new(|| {
     register_histogram!(
@@ -715,6 +741,15 @@ pub static SENT_BATCH_REQUEST_RETRY_COUNT: Lazy<IntCounter> = Lazy::new(|| {
     .unwrap()
 });
 
+/// Count of the number of batch requests delayed by the batch request rate limit.
+pub static SENT_BATCH_REQUEST_THROTTLED_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "quorum_store_sent_batch_request_throttled_count",
+        "Count of the number of batch requests delayed by the batch request rate limit."
+    )
+    .unwrap()
+});
+
 /// Counters(queued,dequeued,dropped) related to batch retrieval per epoch task
 pub static BATCH_RETRIEVAL_TASK_MSGS: Lazy<IntCounterVec> = Lazy::new(|| {
     register_int_counter_vec!(
@@ -779,6 +814,106 @@ pub static RECEIVED_BATCH_RESPONSE_ERROR_COUNT: Lazy<IntCounter> = Lazy::new(||
     .unwrap()
 });
 
+/// Count of the number of batches received from other nodes that exceeded max batch bytes.
+pub static RECEIVED_BATCH_RESPONSE_TOO_LARGE_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "quorum_store_received_batch_response_too_large_count",
+        "Count of the number of batches received from other nodes that exceeded max batch bytes."
+    )
+    .unwrap()
+});
+
+/// Count of the number of batches received from other nodes from a different epoch.
+pub static RECEIVED_BATCH_RESPONSE_WRONG_EPOCH_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "quorum_store_received_batch_response_wrong_epoch_count",
+        "Count of the number of batches received from other nodes from a different epoch."
+    )
+    .unwrap()
+});
+
+/// Count of the number of times a peer reached the consecutive batch request failure threshold.
+pub static BATCH_REQUEST_UNRESPONSIVE_PEER_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "quorum_store_batch_request_unresponsive_peer_count",
+        "Count of the number of times a peer failed to serve consecutive batch requests."
+    )
+    .unwrap()
+});
+
+/// Count of the number of batch requests that failed for exceeding the max total duration.
+pub static BATCH_REQUEST_DEADLINE_EXCEEDED_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "quorum_store_batch_request_deadline_exceeded_count",
+        "Count of the number of batch requests that failed for exceeding the max total duration."
+    )
+    .unwrap()
+});
+
+/// Count of the number of times a peer was excluded from batch requests for a cooldown.
+pub static BATCH_REQUEST_EXCLUDED_PEER_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "quorum_store_batch_request_excluded_peer_count",
+        "Count of the number of times a peer was excluded from batch requests for a cooldown."
+    )
+    .unwrap()
+});
+
+/// Bytes of the batches currently being requested from peers.
+pub static BATCH_REQUEST_OUTSTANDING_BYTES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "quorum_store_batch_request_outstanding_bytes",
+        "Bytes of the batches currently being requested from peers."
+    )
+    .unwrap()
+});
+
+/// Count of the number of low priority batch fetches started while the outstanding bytes budget
+/// was used up, so they wait for earlier fetches to complete.
+pub static BATCH_REQUEST_BUDGET_SATURATED_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "quorum_store_batch_request_budget_saturated_count",
+        "Count of the number of low priority batch fetches started while the outstanding bytes budget was used up."
+    )
+    .unwrap()
+});
+
+/// Number of batches currently being requested from peers.
+pub static BATCH_REQUEST_OUTSTANDING_COUNT: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "quorum_store_batch_request_outstanding_count",
+        "Number of batches currently being requested from peers."
+    )
+    .unwrap()
+});
+
+/// Retries used so far, summed over the batches currently being requested from peers.
+pub static BATCH_REQUEST_PENDING_RETRIES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "quorum_store_batch_request_pending_retries",
+        "Retries used so far, summed over the batches currently being requested from peers."
+    )
+    .unwrap()
+});
+
+/// Number of batches currently being requested from peers that used all their retries.
+pub static BATCH_REQUEST_AT_RETRY_LIMIT_COUNT: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "quorum_store_batch_request_at_retry_limit_count",
+        "Number of batches currently being requested from peers that used all their retries."
+    )
+    .unwrap()
+});
+
+/// Age in ms of the oldest batch currently being requested from peers, as of the last retry.
+pub static BATCH_REQUEST_OLDEST_AGE_MS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "quorum_store_batch_request_oldest_age_ms",
+        "Age in ms of the oldest batch currently being requested from peers, as of the last retry."
+    )
+    .unwrap()
+});
+
 pub static RECEIVED_BATCH_FROM_SUBSCRIPTION_COUNT: Lazy<IntCounter> = Lazy::new(|| {
     register_int_counter!(
         "quorum_store_batch_from_subscription_count",
@@ -850,6 +985,43 @@ pub static GARBAGE_COLLECTED_IN_PROOF_QUEUE_COUNTER: Lazy<IntCounterVec> = Lazy:
     .unwrap()
 });
 
+/// Count of the number of batches created with the digest of a batch created before in the epoch.
+pub static BATCH_DIGEST_COLLISION_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "quorum_store_batch_digest_collision_count",
+        "Count of the number of batches created with the digest of a batch created before in the epoch."
+    )
+    .unwrap()
+});
+
+/// Count of the number of pulled txns dropped for having already expired.
+pub static PULLED_EXPIRED_TXNS_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "quorum_store_pulled_expired_txns_count",
+        "Count of the number of pulled txns dropped for having already expired."
+    )
+    .unwrap()
+});
+
+/// Histogram of the time it takes to pull txns from mempool, including pulls that time out.
+pub static MEMPOOL_PULL_LATENCY: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "quorum_store_mempool_pull_latency_seconds",
+        "Histogram of the time it takes to pull txns from mempool, including pulls that time out.",
+        QUORUM_STORE_LATENCY_BUCKETS.to_vec()
+    )
+    .unwrap()
+});
+
+/// Count of the number of pulls from mempool that timed out.
+pub static MEMPOOL_PULL_TIMEOUT_COUNT: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "quorum_store_mempool_pull_timeout_count",
+        "Count of the number of pulls from mempool that timed out."
+    )
+    .unwrap()
+});
+
 /// Histogram of the time it takes to compute bucketed batches after txns are pulled from mempool.
 pub static BATCH_CREATION_COMPUTE_LATENCY: Lazy<DurationHistogram> = Lazy::new(|| {
     DurationHistogram::new(
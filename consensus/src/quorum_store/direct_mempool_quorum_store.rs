@@ -54,13 +54,15 @@ impl DirectMempoolQuorumStore {
             .into_iter()
             .map(|txn| (txn, TransactionInProgress::new(0)))
             .collect();
-        let msg = QuorumStoreRequest::GetBatchRequest(
-            max_items,
+        let msg = QuorumStoreRequest::GetBatchRequest {
+            max_txns: max_items,
             max_bytes,
             return_non_full,
-            exclude_txns,
+            exclude_transactions: exclude_txns,
+            min_txns: 0,
+            max_wait: Duration::ZERO,
             callback,
-        );
+        };
         self.mempool_sender
             .clone()
             .try_send(msg)
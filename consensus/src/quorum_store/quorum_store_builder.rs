@@ -36,6 +36,7 @@ use aptos_crypto::bls12381::PrivateKey;
 use aptos_logger::prelude::*;
 use aptos_mempool::QuorumStoreRequest;
 use aptos_storage_interface::DbReader;
+use aptos_time_service::TimeService;
 use aptos_types::{
     account_address::AccountAddress, validator_signer::ValidatorSigner,
     validator_verifier::ValidatorVerifier,
@@ -151,6 +152,7 @@ pub struct InnerBuilder {
     batch_reader: Option<Arc<dyn BatchReader>>,
     broadcast_proofs: bool,
     consensus_key: Arc<PrivateKey>,
+    time_service: TimeService,
 }
 
 impl InnerBuilder {
@@ -170,6 +172,7 @@ impl InnerBuilder {
         quorum_store_storage: Arc<dyn QuorumStoreStorage>,
         broadcast_proofs: bool,
         consensus_key: Arc<PrivateKey>,
+        time_service: TimeService,
     ) -> Self {
         let (coordinator_tx, coordinator_rx) = futures_channel::mpsc::channel(config.channel_size);
         let (batch_generator_cmd_tx, batch_generator_cmd_rx) =
@@ -225,10 +228,11 @@ impl InnerBuilder {
             batch_reader: None,
             broadcast_proofs,
             consensus_key,
+            time_service,
         }
     }
 
-    fn create_batch_store(&mut self) -> Arc<BatchReaderImpl<NetworkSender>> {
+    fn create_batch_store(&mut self) -> Arc<BatchReaderImpl<BatchRequester<NetworkSender>>> {
         let signer = ValidatorSigner::new(self.author, self.consensus_key.clone());
 
         let latest_ledger_info_with_sigs = self
@@ -247,7 +251,20 @@ impl InnerBuilder {
             self.config.batch_request_rpc_timeout_ms,
             self.network_sender.clone(),
             self.verifier.clone(),
-        );
+        )
+        .with_all_signers_on_last_retry(self.config.batch_request_all_signers_on_last_retry)
+        .with_peer_failure_threshold(self.config.batch_request_peer_failure_threshold)
+        .with_peer_cooldown(Duration::from_millis(
+            self.config.batch_request_peer_cooldown_ms,
+        ))
+        .with_max_total_duration(Duration::from_millis(
+            self.config.batch_request_max_total_duration_ms,
+        ))
+        .with_request_rate_limit(
+            self.config.batch_request_max_rate_per_sec,
+            self.config.batch_request_rate_burst,
+        )
+        .with_max_batch_bytes(self.config.receiver_max_batch_bytes as u64);
         let batch_store = Arc::new(BatchStore::new(
             self.epoch,
             is_new_epoch,
@@ -260,7 +277,11 @@ impl InnerBuilder {
             Duration::from_secs(60).as_micros() as u64,
         ));
         self.batch_store = Some(batch_store.clone());
-        let batch_reader = Arc::new(BatchReaderImpl::new(batch_store.clone(), batch_requester));
+        let batch_reader = Arc::new(BatchReaderImpl::new(
+            batch_store.clone(),
+            batch_requester,
+            self.config.batch_request_max_outstanding_bytes as u64,
+        ));
         self.batch_reader = Some(batch_reader.clone());
 
         batch_reader
@@ -286,6 +307,7 @@ impl InnerBuilder {
             self.proof_coordinator_cmd_tx.clone(),
             self.proof_manager_cmd_tx.clone(),
             self.quorum_store_msg_tx.clone(),
+            self.batch_reader.clone().unwrap(),
         );
         spawn_named!(
             "quorum_store_coordinator",
@@ -302,7 +324,8 @@ impl InnerBuilder {
             self.batch_store.clone().unwrap(),
             self.quorum_store_to_mempool_sender,
             self.mempool_txn_pull_timeout_ms,
-        );
+        )
+        .with_time_service(self.time_service.clone());
         spawn_named!(
             "batch_generator",
             batch_generator.start(
@@ -5,7 +5,8 @@ use crate::{
     monitor,
     quorum_store::{
         batch_coordinator::BatchCoordinatorCommand, batch_generator::BatchGeneratorCommand,
-        counters, proof_coordinator::ProofCoordinatorCommand, proof_manager::ProofManagerCommand,
+        batch_store::BatchReader, counters, proof_coordinator::ProofCoordinatorCommand,
+        proof_manager::ProofManagerCommand,
     },
     round_manager::VerifiedEvent,
 };
@@ -14,6 +15,7 @@ use aptos_consensus_types::{common::Author, proof_of_store::BatchInfo};
 use aptos_logger::prelude::*;
 use aptos_types::{account_address::AccountAddress, PeerId};
 use futures::StreamExt;
+use std::sync::Arc;
 use tokio::sync::{mpsc, oneshot};
 
 pub enum CoordinatorCommand {
@@ -28,6 +30,7 @@ pub struct QuorumStoreCoordinator {
     proof_coordinator_cmd_tx: mpsc::Sender<ProofCoordinatorCommand>,
     proof_manager_cmd_tx: mpsc::Sender<ProofManagerCommand>,
     quorum_store_msg_tx: aptos_channel::Sender<AccountAddress, (Author, VerifiedEvent)>,
+    batch_reader: Arc<dyn BatchReader>,
 }
 
 impl QuorumStoreCoordinator {
@@ -38,6 +41,7 @@ impl QuorumStoreCoordinator {
         proof_coordinator_cmd_tx: mpsc::Sender<ProofCoordinatorCommand>,
         proof_manager_cmd_tx: mpsc::Sender<ProofManagerCommand>,
         quorum_store_msg_tx: aptos_channel::Sender<AccountAddress, (Author, VerifiedEvent)>,
+        batch_reader: Arc<dyn BatchReader>,
     ) -> Self {
         Self {
             my_peer_id,
@@ -46,6 +50,7 @@ impl QuorumStoreCoordinator {
             proof_coordinator_cmd_tx,
             proof_manager_cmd_tx,
             quorum_store_msg_tx,
+            batch_reader,
         }
     }
 
@@ -155,6 +160,9 @@ impl QuorumStoreCoordinator {
                             .await
                             .expect("Failed to stop ProofManager");
 
+                        // Fail the outstanding batch fetches instead of leaving them to time out
+                        self.batch_reader.shutdown();
+
                         ack_tx
                             .send(())
                             .expect("Failed to send shutdown ack from QuorumStore");
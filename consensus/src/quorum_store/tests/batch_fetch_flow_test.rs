@@ -0,0 +1,166 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    network::QuorumStoreSender,
+    quorum_store::{
+        batch_generator::BatchGenerator,
+        batch_requester::BatchRetriever,
+        batch_store::BatchWriter,
+        quorum_store_db::MockQuorumStoreDB,
+        tests::batch_requester_test::batch_requester_for_test,
+        types::{Batch, BatchRequest, BatchResponse, PersistedValue},
+    },
+    test_utils::create_vec_signed_transactions,
+};
+use aptos_config::config::QuorumStoreConfig;
+use aptos_consensus_types::{
+    common::Author,
+    proof_of_store::{ProofOfStore, SignedBatchInfo},
+};
+use aptos_crypto::HashValue;
+use aptos_infallible::Mutex;
+use aptos_mempool::{QuorumStoreRequest, QuorumStoreResponse};
+use aptos_types::validator_verifier::random_validator_verifier;
+use futures::{channel::mpsc::channel, StreamExt};
+use maplit::btreeset;
+use move_core_types::account_address::AccountAddress;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::sync::oneshot;
+
+struct NoopBatchWriter {}
+
+impl BatchWriter for NoopBatchWriter {
+    fn persist(&self, _persist_requests: Vec<PersistedValue>) -> Vec<SignedBatchInfo> {
+        vec![]
+    }
+}
+
+/// Serves the batches it was given, except that requests to timeout_peer fail.
+#[derive(Clone)]
+struct MockBatchServer {
+    batches: Arc<HashMap<HashValue, Batch>>,
+    timeout_peer: Author,
+    requested_peers: Arc<Mutex<Vec<Author>>>,
+}
+
+#[async_trait::async_trait]
+impl QuorumStoreSender for MockBatchServer {
+    async fn request_batch(
+        &self,
+        request: BatchRequest,
+        recipient: Author,
+        _timeout: Duration,
+    ) -> anyhow::Result<BatchResponse> {
+        self.requested_peers.lock().push(recipient);
+        if recipient == self.timeout_peer {
+            anyhow::bail!("rpc timeout");
+        }
+        let batch = self
+            .batches
+            .get(&request.digest())
+            .ok_or_else(|| anyhow::anyhow!("unknown batch"))?;
+        Ok(BatchResponse::Batch(batch.clone()))
+    }
+
+    async fn send_signed_batch_info_msg(
+        &self,
+        _signed_batch_infos: Vec<SignedBatchInfo>,
+        _recipients: Vec<Author>,
+    ) {
+        unimplemented!()
+    }
+
+    async fn broadcast_batch_msg(&mut self, _batches: Vec<Batch>) {
+        unimplemented!()
+    }
+
+    async fn broadcast_proof_of_store_msg(&mut self, _proof_of_stores: Vec<ProofOfStore>) {
+        unimplemented!()
+    }
+
+    async fn send_proof_of_store_msg_to_self(&mut self, _proof_of_stores: Vec<ProofOfStore>) {
+        unimplemented!()
+    }
+}
+
+/// Pulls transactions from a mock mempool into batches, and requests the batches back from mock
+/// peers, to exercise the batch pipeline end to end.
+#[tokio::test]
+async fn test_batch_fetch_flow() {
+    let (mempool_tx, mut mempool_rx) = channel(10);
+    let txns = create_vec_signed_transactions(10);
+    let mempool_txns = txns.clone();
+    tokio::spawn(async move {
+        if let Some(QuorumStoreRequest::GetBatchRequest { callback, .. }) = mempool_rx.next().await
+        {
+            callback
+                .send(Ok(QuorumStoreResponse::GetBatchResponse(mempool_txns)))
+                .unwrap();
+        }
+    });
+
+    let mut batch_generator = BatchGenerator::new(
+        1,
+        AccountAddress::random(),
+        QuorumStoreConfig {
+            sender_max_batch_txns: 4,
+            ..Default::default()
+        },
+        Arc::new(MockQuorumStoreDB::new()),
+        Arc::new(NoopBatchWriter {}),
+        mempool_tx,
+        1_000,
+    );
+    let batches = batch_generator.handle_scheduled_pull(100).await;
+    assert_eq!(batches.len(), 3);
+
+    // Requests start from the smallest signer, which times out, so every batch is retried
+    let mut signers = [AccountAddress::random(), AccountAddress::random()];
+    signers.sort();
+    let [timeout_peer, serving_peer] = signers;
+    let network_sender = MockBatchServer {
+        batches: Arc::new(
+            batches
+                .iter()
+                .map(|batch| (*batch.digest(), batch.clone()))
+                .collect(),
+        ),
+        timeout_peer,
+        requested_peers: Arc::new(Mutex::new(vec![])),
+    };
+    let (_signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let batch_requester = batch_requester_for_test(
+        1,
+        3,
+        1_000,
+        network_sender.clone(),
+        validator_verifier.into(),
+    )
+    .with_deterministic_peer_order();
+
+    let mut fetched_txns = vec![];
+    for batch in &batches {
+        let (_, subscriber_rx) = oneshot::channel();
+        let (payload, peer) = batch_requester
+            .request_batch(
+                *batch.digest(),
+                batch.expiration(),
+                Arc::new(Mutex::new(btreeset![timeout_peer, serving_peer])),
+                subscriber_rx,
+            )
+            .await
+            .unwrap();
+        assert_eq!(peer, Some(serving_peer));
+        assert_eq!(payload, batch.txns());
+        fetched_txns.extend(payload);
+    }
+
+    // The batches reconstruct all the pulled transactions
+    assert_eq!(fetched_txns.len(), txns.len());
+    assert!(txns.iter().all(|txn| fetched_txns.contains(txn)));
+    assert_eq!(
+        network_sender.requested_peers.lock().clone(),
+        [timeout_peer, serving_peer].repeat(batches.len())
+    );
+}
@@ -3,12 +3,16 @@
 
 use crate::{
     quorum_store::{
-        batch_coordinator::BatchCoordinatorCommand, batch_generator::BatchGenerator,
-        batch_store::BatchWriter, quorum_store_db::MockQuorumStoreDB, types::PersistedValue,
+        batch_coordinator::BatchCoordinatorCommand,
+        batch_generator::{num_txns_within_limits, num_txns_within_weight_limits, BatchGenerator},
+        batch_store::BatchWriter,
+        counters,
+        quorum_store_db::MockQuorumStoreDB,
+        types::PersistedValue,
     },
     test_utils::{
-        create_signed_transaction, create_vec_signed_transactions,
-        create_vec_signed_transactions_with_gas,
+        create_signed_transaction, create_signed_transaction_with_expiration,
+        create_vec_signed_transactions, create_vec_signed_transactions_with_gas,
     },
 };
 use aptos_config::config::QuorumStoreConfig;
@@ -17,7 +21,9 @@ use aptos_consensus_types::{
     proof_of_store::{BatchId, SignedBatchInfo},
 };
 use aptos_mempool::{QuorumStoreRequest, QuorumStoreResponse};
+use aptos_time_service::TimeService;
 use aptos_types::transaction::SignedTransaction;
+use claims::assert_ge;
 use futures::{
     channel::mpsc::{channel, Receiver},
     StreamExt,
@@ -46,13 +52,11 @@ async fn queue_mempool_batch_response(
     max_size: usize,
     quorum_store_to_mempool_receiver: &mut Receiver<QuorumStoreRequest>,
 ) -> BTreeMap<TransactionSummary, TransactionInProgress> {
-    if let QuorumStoreRequest::GetBatchRequest(
-        _max_batch_size,
-        _max_bytes,
-        _return_non_full,
-        exclude_txns,
+    if let QuorumStoreRequest::GetBatchRequest {
+        exclude_transactions: exclude_txns,
         callback,
-    ) = timeout(
+        ..
+    } = timeout(
         Duration::from_millis(1_000),
         quorum_store_to_mempool_receiver.select_next_some(),
     )
@@ -382,6 +386,157 @@ async fn test_max_batch_txns() {
         .unwrap();
 }
 
+#[tokio::test]
+async fn test_digest_collision_check() {
+    let (quorum_store_to_mempool_tx, mut quorum_store_to_mempool_rx) = channel(1_024);
+
+    let config = QuorumStoreConfig {
+        batch_generation_check_digest_collisions: true,
+        ..Default::default()
+    };
+    let max_batch_bytes = config.sender_max_batch_bytes;
+    let mut batch_generator = BatchGenerator::new(
+        0,
+        AccountAddress::random(),
+        config,
+        Arc::new(MockQuorumStoreDB::new()),
+        Arc::new(MockBatchWriter::new()),
+        quorum_store_to_mempool_tx,
+        1000,
+    );
+
+    // Mempool returns the same txns twice, so the two batches have the same payload
+    let txns = create_vec_signed_transactions(5);
+    let join_handle = tokio::spawn(async move {
+        for _ in 0..2 {
+            queue_mempool_batch_response(
+                txns.clone(),
+                max_batch_bytes,
+                &mut quorum_store_to_mempool_rx,
+            )
+            .await;
+        }
+    });
+    let num_collisions = counters::BATCH_DIGEST_COLLISION_COUNT.get();
+    let first_batches = batch_generator.handle_scheduled_pull(300).await;
+    let second_batches = batch_generator.handle_scheduled_pull(300).await;
+    join_handle.await.unwrap();
+
+    assert_eq!(first_batches.len(), 1);
+    assert_eq!(second_batches.len(), 1);
+    assert_ne!(first_batches[0].batch_id(), second_batches[0].batch_id());
+    assert_eq!(first_batches[0].digest(), second_batches[0].digest());
+    // Other tests may create batches concurrently, so only check that the collision was counted
+    assert_ge!(
+        counters::BATCH_DIGEST_COLLISION_COUNT.get(),
+        num_collisions + 1
+    );
+}
+
+#[tokio::test]
+async fn test_expired_txn_filter() {
+    let (quorum_store_to_mempool_tx, mut quorum_store_to_mempool_rx) = channel(1_024);
+
+    let time_service = TimeService::mock();
+    time_service.clone().into_mock().advance_secs(10);
+    let config = QuorumStoreConfig {
+        batch_generation_drop_expired_txns: true,
+        ..Default::default()
+    };
+    let max_batch_bytes = config.sender_max_batch_bytes;
+    let mut batch_generator = BatchGenerator::new(
+        0,
+        AccountAddress::random(),
+        config,
+        Arc::new(MockQuorumStoreDB::new()),
+        Arc::new(MockBatchWriter::new()),
+        quorum_store_to_mempool_tx,
+        1000,
+    )
+    .with_time_service(time_service);
+
+    let expired_txn = create_signed_transaction_with_expiration(1, 5);
+    let valid_txn = create_signed_transaction_with_expiration(1, 100);
+    let pulled_txns = vec![expired_txn.clone(), valid_txn.clone()];
+    let join_handle = tokio::spawn(async move {
+        queue_mempool_batch_response(
+            pulled_txns,
+            max_batch_bytes,
+            &mut quorum_store_to_mempool_rx,
+        )
+        .await;
+        queue_mempool_batch_response(vec![], max_batch_bytes, &mut quorum_store_to_mempool_rx).await
+    });
+    let batches = batch_generator.handle_scheduled_pull(300).await;
+    // Only the txn that hasn't expired yet is batched
+    assert_eq!(batches.len(), 1);
+    assert_eq!(batches[0].txns(), [valid_txn.clone()]);
+
+    // The expired txn is excluded from the next pull, as well as the batched one
+    assert!(batch_generator.handle_scheduled_pull(300).await.is_empty());
+    let exclude_txns = join_handle.await.unwrap();
+    let summary = |txn: &SignedTransaction| {
+        TransactionSummary::new(txn.sender(), txn.replay_protector(), txn.committed_hash())
+    };
+    assert_eq!(exclude_txns.len(), 2);
+    assert!(exclude_txns.contains_key(&summary(&expired_txn)));
+    assert!(exclude_txns.contains_key(&summary(&valid_txn)));
+}
+
+#[tokio::test]
+async fn test_min_pull_txns() {
+    let (quorum_store_to_mempool_tx, mut quorum_store_to_mempool_rx) = channel(1_024);
+
+    let config = QuorumStoreConfig {
+        sender_min_pull_txns: 5,
+        sender_min_pull_max_wait_ms: 200,
+        ..Default::default()
+    };
+    let mut batch_generator = BatchGenerator::new(
+        0,
+        AccountAddress::random(),
+        config,
+        Arc::new(MockQuorumStoreDB::new()),
+        Arc::new(MockBatchWriter::new()),
+        quorum_store_to_mempool_tx,
+        1000,
+    );
+
+    let signed_txns = create_vec_signed_transactions(5);
+    let mempool_txns = signed_txns.clone();
+    let join_handle = tokio::spawn(async move {
+        let mut min_pulls = vec![];
+        while let Some(QuorumStoreRequest::GetBatchRequest {
+            max_txns: max_items,
+            min_txns,
+            max_wait,
+            callback,
+            ..
+        }) = quorum_store_to_mempool_rx.next().await
+        {
+            min_pulls.push((max_items, min_txns, max_wait));
+            callback
+                .send(Ok(QuorumStoreResponse::GetBatchResponse(
+                    mempool_txns.clone(),
+                )))
+                .unwrap();
+        }
+        min_pulls
+    });
+
+    let batches = batch_generator.handle_scheduled_pull(300).await;
+    assert_eq!(batches.len(), 1);
+    assert_eq!(batches[0].txns(), signed_txns);
+    // The min is capped by the number of txns pulled
+    batch_generator.handle_scheduled_pull(3).await;
+    drop(batch_generator);
+
+    assert_eq!(join_handle.await.unwrap(), vec![
+        (300, 5, Duration::from_millis(200)),
+        (3, 3, Duration::from_millis(200)),
+    ]);
+}
+
 #[tokio::test]
 async fn test_max_batch_bytes() {
     let (quorum_store_to_mempool_tx, mut quorum_store_to_mempool_rx) = channel(1_024);
@@ -785,3 +940,133 @@ async fn test_remote_batches_in_progress() {
         .unwrap()
         .unwrap();
 }
+
+#[test]
+fn test_num_txns_within_limits() {
+    let txns = create_vec_signed_transactions(5);
+    let txn_bytes = txns[0].txn_bytes_len() as u64;
+    assert!(txns
+        .iter()
+        .all(|txn| txn.txn_bytes_len() as u64 == txn_bytes));
+
+    assert_eq!(num_txns_within_limits(&txns, 10, 10 * txn_bytes), 5);
+    // limited by count
+    assert_eq!(num_txns_within_limits(&txns, 3, 10 * txn_bytes), 3);
+    // limited by bytes, stopping at the first txn that doesn't fit
+    assert_eq!(num_txns_within_limits(&txns, 10, 2 * txn_bytes + 1), 2);
+    assert_eq!(num_txns_within_limits(&txns, 10, txn_bytes - 1), 0);
+    assert_eq!(num_txns_within_limits(&[], 10, 10 * txn_bytes), 0);
+}
+
+#[test]
+fn test_num_txns_within_weight_limits() {
+    let txns = create_vec_signed_transactions(5);
+    let heavy_sender = txns[1].sender();
+    // txns from heavy_sender weigh 10, the rest weigh 1
+    let weight_fn = |txn: &SignedTransaction| {
+        if txn.sender() == heavy_sender {
+            10
+        } else {
+            1
+        }
+    };
+
+    let txn_bytes = txns[0].txn_bytes_len() as u64;
+    let max_bytes = 10 * txn_bytes;
+
+    assert_eq!(
+        num_txns_within_weight_limits(&txns, 10, max_bytes, 14, weight_fn),
+        5
+    );
+    assert_eq!(
+        num_txns_within_weight_limits(&txns, 10, max_bytes, 11, weight_fn),
+        2
+    );
+    assert_eq!(
+        num_txns_within_weight_limits(&txns, 10, max_bytes, 10, weight_fn),
+        1
+    );
+    // a txn that weighs more than the limit on its own still fills a batch
+    assert_eq!(
+        num_txns_within_weight_limits(&txns[1..], 10, max_bytes, 5, weight_fn),
+        1
+    );
+    // the size still limits txns that weigh less than their size
+    assert_eq!(
+        num_txns_within_weight_limits(&txns, 10, 3 * txn_bytes, 14, weight_fn),
+        3
+    );
+}
+
+#[tokio::test]
+async fn test_batch_txn_weight_overhead() {
+    let (quorum_store_to_mempool_tx, mut quorum_store_to_mempool_rx) = channel(1_024);
+
+    let txn_bytes_len = create_vec_signed_transactions(1)[0].txn_bytes_len();
+    // every txn weighs twice its size
+    let config = QuorumStoreConfig {
+        sender_max_batch_bytes: txn_bytes_len * 10,
+        sender_batch_txn_weight_overhead: txn_bytes_len,
+        ..Default::default()
+    };
+    let mut batch_generator = BatchGenerator::new(
+        0,
+        AccountAddress::random(),
+        config,
+        Arc::new(MockQuorumStoreDB::new()),
+        Arc::new(MockBatchWriter::new()),
+        quorum_store_to_mempool_tx,
+        1000,
+    );
+
+    let join_handle = tokio::spawn(async move {
+        queue_mempool_batch_response(
+            create_vec_signed_transactions(15),
+            txn_bytes_len * 15,
+            &mut quorum_store_to_mempool_rx,
+        )
+        .await;
+    });
+    let batches = batch_generator.handle_scheduled_pull(300).await;
+    join_handle.await.unwrap();
+
+    let batch_sizes: Vec<_> = batches.iter().map(|batch| batch.num_txns()).collect();
+    assert_eq!(batch_sizes, vec![5, 5, 5]);
+}
+
+#[tokio::test]
+async fn test_batch_txn_weight_fn_within_max_bytes() {
+    let (quorum_store_to_mempool_tx, mut quorum_store_to_mempool_rx) = channel(1_024);
+
+    let txn_bytes_len = create_vec_signed_transactions(1)[0].txn_bytes_len();
+    let config = QuorumStoreConfig {
+        sender_max_batch_bytes: txn_bytes_len * 10,
+        ..Default::default()
+    };
+    // every txn weighs far less than its size
+    let mut batch_generator = BatchGenerator::new(
+        0,
+        AccountAddress::random(),
+        config,
+        Arc::new(MockQuorumStoreDB::new()),
+        Arc::new(MockBatchWriter::new()),
+        quorum_store_to_mempool_tx,
+        1000,
+    )
+    .with_txn_weight_fn(|_| 1);
+
+    let join_handle = tokio::spawn(async move {
+        queue_mempool_batch_response(
+            create_vec_signed_transactions(15),
+            txn_bytes_len * 15,
+            &mut quorum_store_to_mempool_rx,
+        )
+        .await;
+    });
+    let batches = batch_generator.handle_scheduled_pull(300).await;
+    join_handle.await.unwrap();
+
+    // batches are still limited to sender_max_batch_bytes of size
+    let batch_sizes: Vec<_> = batches.iter().map(|batch| batch.num_txns()).collect();
+    assert_eq!(batch_sizes, vec![10, 5]);
+}
@@ -4,8 +4,11 @@
 use crate::{
     network::QuorumStoreSender,
     quorum_store::{
-        batch_requester::BatchRequester,
+        batch_requester::{
+            BatchRequestFailure, BatchRequester, BatchRequesterHealth, BatchRetriever,
+        },
         types::{Batch, BatchRequest, BatchResponse},
+        utils::QuorumStoreRng,
     },
     test_utils::create_vec_signed_transactions,
 };
@@ -19,26 +22,63 @@ use aptos_types::{
     aggregate_signature::PartialSignatures,
     block_info::BlockInfo,
     ledger_info::{LedgerInfo, LedgerInfoWithSignatures},
+    transaction::SignedTransaction,
     validator_signer::ValidatorSigner,
     validator_verifier::{ValidatorConsensusInfo, ValidatorVerifier},
 };
-use claims::{assert_err, assert_ok_eq};
+use claims::{assert_err, assert_err_eq, assert_ok_eq};
+use futures::future::abortable;
 use maplit::btreeset;
 use move_core_types::account_address::AccountAddress;
 use std::{
+    collections::{BTreeSet, HashMap},
     sync::Arc,
-    time::{Duration, Instant},
+    time::Duration,
 };
-use tokio::sync::oneshot;
+use tokio::{sync::oneshot, time::Instant};
 
 #[derive(Clone)]
 struct MockBatchRequester {
-    return_value: BatchResponse,
+    return_value: Option<BatchResponse>,
+    failing_peers: BTreeSet<Author>,
+    // overrides return_value for the given peers
+    peer_responses: HashMap<Author, BatchResponse>,
+    requested_peers: Arc<Mutex<Vec<Author>>>,
 }
 
 impl MockBatchRequester {
     fn new(return_value: BatchResponse) -> Self {
-        Self { return_value }
+        Self::new_with_failing_peers(return_value, BTreeSet::new())
+    }
+
+    fn new_with_failing_peers(
+        return_value: BatchResponse,
+        failing_peers: BTreeSet<Author>,
+    ) -> Self {
+        Self {
+            return_value: Some(return_value),
+            failing_peers,
+            peer_responses: HashMap::new(),
+            requested_peers: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    fn new_failing() -> Self {
+        Self {
+            return_value: None,
+            failing_peers: BTreeSet::new(),
+            peer_responses: HashMap::new(),
+            requested_peers: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    fn with_peer_response(mut self, peer: Author, response: BatchResponse) -> Self {
+        self.peer_responses.insert(peer, response);
+        self
+    }
+
+    fn requested_peers(&self) -> Vec<Author> {
+        self.requested_peers.lock().clone()
     }
 }
 
@@ -47,10 +87,19 @@ impl QuorumStoreSender for MockBatchRequester {
     async fn request_batch(
         &self,
         _request: BatchRequest,
-        _recipient: Author,
+        recipient: Author,
         _timeout: Duration,
     ) -> anyhow::Result<BatchResponse> {
-        Ok(self.return_value.clone())
+        self.requested_peers.lock().push(recipient);
+        if self.failing_peers.contains(&recipient) {
+            anyhow::bail!("network send failed");
+        }
+        if let Some(response) = self.peer_responses.get(&recipient) {
+            return Ok(response.clone());
+        }
+        self.return_value
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("network send failed"))
     }
 
     async fn send_signed_batch_info_msg(
@@ -74,6 +123,26 @@ impl QuorumStoreSender for MockBatchRequester {
     }
 }
 
+/// A requester with a 1s rpc timeout and none of the optional features enabled.
+pub(crate) fn batch_requester_for_test<T: QuorumStoreSender + Sync + 'static>(
+    request_num_peers: usize,
+    retry_limit: usize,
+    retry_interval_ms: usize,
+    network_sender: T,
+    validator_verifier: Arc<ValidatorVerifier>,
+) -> BatchRequester<T> {
+    BatchRequester::new(
+        1,
+        AccountAddress::random(),
+        request_num_peers,
+        retry_limit,
+        retry_interval_ms,
+        1_000,
+        network_sender,
+        validator_verifier,
+    )
+}
+
 #[tokio::test]
 async fn test_batch_request_exists() {
     let txns = create_vec_signed_transactions(1);
@@ -88,28 +157,27 @@ async fn test_batch_request_exists() {
     let batch_response = BatchResponse::Batch(batch.clone());
 
     let validator_signer = ValidatorSigner::random(None);
-    let batch_requester = BatchRequester::new(
-        1,
-        AccountAddress::random(),
+    let batch_requester = batch_requester_for_test(
         1,
         2,
         1_000,
-        1_000,
         MockBatchRequester::new(batch_response),
         ValidatorVerifier::new_single(validator_signer.author(), validator_signer.public_key())
             .into(),
     );
 
+    let peer = AccountAddress::random();
     let (_, subscriber_rx) = oneshot::channel();
     let result = batch_requester
         .request_batch(
             *batch.digest(),
             batch.expiration(),
-            Arc::new(Mutex::new(btreeset![AccountAddress::random()])),
+            Arc::new(Mutex::new(btreeset![peer])),
             subscriber_rx,
         )
         .await;
-    assert_ok_eq!(result, txns);
+    // The serving peer is returned along with the payload
+    assert_ok_eq!(result, (txns, Some(peer)));
 }
 
 fn create_ledger_info_with_timestamp(
@@ -178,13 +246,10 @@ async fn test_batch_request_not_exists_not_expired() {
         0,
     );
     let batch_response = BatchResponse::NotFound(ledger_info_with_signatures);
-    let batch_requester = BatchRequester::new(
-        1,
-        AccountAddress::random(),
+    let batch_requester = batch_requester_for_test(
         1,
         2,
         retry_interval_ms,
-        1_000,
         MockBatchRequester::new(batch_response),
         validator_verifier.into(),
     );
@@ -205,6 +270,132 @@ async fn test_batch_request_not_exists_not_expired() {
     assert!(request_duration > Duration::from_millis(retry_interval_ms as u64));
 }
 
+#[tokio::test(start_paused = true)]
+async fn test_batch_request_not_found_tries_another_peer() {
+    let retry_interval_ms = 1_000;
+    let expiration = 10_000;
+
+    // Batch has not expired yet
+    let (ledger_info_with_signatures, validator_verifier) =
+        create_ledger_info_with_timestamp(expiration - 1);
+
+    let txns = create_vec_signed_transactions(1);
+    let batch = Batch::new(
+        BatchId::new_for_test(1),
+        txns.clone(),
+        1,
+        expiration,
+        AccountAddress::random(),
+        0,
+    );
+    let mut signers = [AccountAddress::random(), AccountAddress::random()];
+    signers.sort();
+    let [missing_peer, serving_peer] = signers;
+    let network_sender = MockBatchRequester::new(BatchResponse::Batch(batch.clone()))
+        .with_peer_response(
+            missing_peer,
+            BatchResponse::NotFound(ledger_info_with_signatures),
+        );
+    let batch_requester = batch_requester_for_test(
+        1,
+        3,
+        retry_interval_ms,
+        network_sender.clone(),
+        validator_verifier.into(),
+    )
+    .with_deterministic_peer_order();
+
+    let request_start = Instant::now();
+    let (_, subscriber_rx) = oneshot::channel();
+    let result = batch_requester
+        .request_batch(
+            *batch.digest(),
+            batch.expiration(),
+            Arc::new(Mutex::new(btreeset![missing_peer, serving_peer])),
+            subscriber_rx,
+        )
+        .await;
+    let request_duration = request_start.elapsed();
+    assert_ok_eq!(result, (txns, Some(serving_peer)));
+    // The miss is followed by a request to the other signer without waiting for the retry
+    assert!(request_duration < Duration::from_millis(retry_interval_ms as u64));
+    assert_eq!(network_sender.requested_peers(), vec![
+        missing_peer,
+        serving_peer
+    ]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_batch_request_not_found_skips_missing_peers() {
+    let retry_interval_ms = 1_000;
+    let expiration = 10_000;
+
+    // Batch has not expired yet
+    let (ledger_info_with_signatures, validator_verifier) =
+        create_ledger_info_with_timestamp(expiration - 1);
+
+    let txns = create_vec_signed_transactions(1);
+    let batch = Batch::new(
+        BatchId::new_for_test(1),
+        txns.clone(),
+        1,
+        expiration,
+        AccountAddress::random(),
+        0,
+    );
+    let mut signers = [
+        AccountAddress::random(),
+        AccountAddress::random(),
+        AccountAddress::random(),
+    ];
+    signers.sort();
+    let [first_missing_peer, second_missing_peer, serving_peer] = signers;
+    let network_sender = MockBatchRequester::new(BatchResponse::Batch(batch.clone()))
+        .with_peer_response(
+            first_missing_peer,
+            BatchResponse::NotFound(ledger_info_with_signatures.clone()),
+        )
+        .with_peer_response(
+            second_missing_peer,
+            BatchResponse::NotFound(ledger_info_with_signatures),
+        );
+    let batch_requester = batch_requester_for_test(
+        2,
+        3,
+        retry_interval_ms,
+        network_sender.clone(),
+        validator_verifier.into(),
+    )
+    .with_deterministic_peer_order();
+
+    let request_start = Instant::now();
+    let (_, subscriber_rx) = oneshot::channel();
+    let result = batch_requester
+        .request_batch(
+            *batch.digest(),
+            batch.expiration(),
+            Arc::new(Mutex::new(btreeset![
+                first_missing_peer,
+                second_missing_peer,
+                serving_peer
+            ])),
+            subscriber_rx,
+        )
+        .await;
+    let request_duration = request_start.elapsed();
+    assert_ok_eq!(result, (txns, Some(serving_peer)));
+    // Once both requests missed the batch, only the signer that didn't miss it yet is requested,
+    // without waiting for the retry interval
+    assert!(request_duration < Duration::from_millis(retry_interval_ms as u64));
+    let mut requested_peers = network_sender.requested_peers();
+    requested_peers.sort();
+    assert_eq!(requested_peers, vec![
+        first_missing_peer,
+        second_missing_peer,
+        serving_peer
+    ]);
+}
+
 #[tokio::test]
 async fn test_batch_request_not_exists_expired() {
     let retry_interval_ms = 1_000;
@@ -223,13 +414,10 @@ async fn test_batch_request_not_exists_expired() {
         0,
     );
     let batch_response = BatchResponse::NotFound(ledger_info_with_signatures);
-    let batch_requester = BatchRequester::new(
-        1,
-        AccountAddress::random(),
+    let batch_requester = batch_requester_for_test(
         1,
         2,
         retry_interval_ms,
-        1_000,
         MockBatchRequester::new(batch_response),
         validator_verifier.into(),
     );
@@ -249,3 +437,798 @@ async fn test_batch_request_not_exists_expired() {
     // No retry because of short-circuiting of expired batch
     assert!(request_duration < Duration::from_millis(retry_interval_ms as u64));
 }
+
+#[tokio::test(start_paused = true)]
+async fn test_batch_request_network_failure() {
+    let retry_interval_ms = 1_000;
+    let retry_limit = 3;
+
+    let validator_signer = ValidatorSigner::random(None);
+    let network_sender = MockBatchRequester::new_failing();
+    let batch_requester = batch_requester_for_test(
+        1,
+        retry_limit,
+        retry_interval_ms,
+        network_sender.clone(),
+        ValidatorVerifier::new_single(validator_signer.author(), validator_signer.public_key())
+            .into(),
+    );
+
+    let request_start = Instant::now();
+    let (_, subscriber_rx) = oneshot::channel();
+    let result = batch_requester
+        .request_batch(
+            HashValue::random(),
+            0,
+            Arc::new(Mutex::new(btreeset![
+                AccountAddress::random(),
+                AccountAddress::random()
+            ])),
+            subscriber_rx,
+        )
+        .await;
+    let request_duration = request_start.elapsed();
+    assert_err!(result);
+    // Both signers fail right away in every retry, and each retry after the first waits for the
+    // retry interval instead of starting as soon as the previous one failed
+    assert_eq!(network_sender.requested_peers().len(), 2 * retry_limit);
+    assert!(
+        request_duration >= Duration::from_millis(((retry_limit - 1) * retry_interval_ms) as u64)
+    );
+}
+
+#[tokio::test]
+async fn test_batch_request_contacted_peers() {
+    let expiration = 10_000;
+
+    // Batch is never found and has not expired, so all retries are used
+    let (ledger_info_with_signatures, validator_verifier) =
+        create_ledger_info_with_timestamp(expiration - 1);
+    let network_sender =
+        MockBatchRequester::new(BatchResponse::NotFound(ledger_info_with_signatures));
+    let batch_requester =
+        batch_requester_for_test(2, 2, 100, network_sender.clone(), validator_verifier.into());
+
+    let signers = btreeset![
+        AccountAddress::random(),
+        AccountAddress::random(),
+        AccountAddress::random()
+    ];
+    let (_, subscriber_rx) = oneshot::channel();
+    let result = batch_requester
+        .request_batch(
+            HashValue::random(),
+            expiration,
+            Arc::new(Mutex::new(signers.clone())),
+            subscriber_rx,
+        )
+        .await;
+    assert_err!(result);
+
+    // request_num_peers peers per retry, cycling through the signers
+    let requested_peers = network_sender.requested_peers();
+    assert_eq!(requested_peers.len(), 4);
+    assert!(requested_peers.iter().all(|peer| signers.contains(peer)));
+    assert_ne!(requested_peers[0], requested_peers[1]);
+    assert_ne!(requested_peers[2], requested_peers[3]);
+}
+
+#[tokio::test]
+async fn test_batch_request_deterministic_peer_order() {
+    let expiration = 10_000;
+    let (ledger_info_with_signatures, validator_verifier) =
+        create_ledger_info_with_timestamp(expiration - 1);
+    let validator_verifier = Arc::new(validator_verifier);
+
+    let mut peers: Vec<_> = (0..4).map(|_| AccountAddress::random()).collect();
+    let mut requested_peer_sequences = vec![];
+    for _ in 0..2 {
+        let network_sender =
+            MockBatchRequester::new(BatchResponse::NotFound(ledger_info_with_signatures.clone()));
+        let batch_requester =
+            batch_requester_for_test(1, 3, 10, network_sender.clone(), validator_verifier.clone())
+                .with_deterministic_peer_order();
+
+        let (_, subscriber_rx) = oneshot::channel();
+        let result = batch_requester
+            .request_batch(
+                HashValue::random(),
+                expiration,
+                Arc::new(Mutex::new(peers.iter().cloned().collect())),
+                subscriber_rx,
+            )
+            .await;
+        assert_err!(result);
+        requested_peer_sequences.push(network_sender.requested_peers());
+        peers.reverse();
+    }
+
+    // Both requests cycle through the signers from the smallest PeerId
+    peers.sort();
+    assert_eq!(requested_peer_sequences[0], peers[..3].to_vec());
+    assert_eq!(requested_peer_sequences[0], requested_peer_sequences[1]);
+}
+
+#[tokio::test]
+async fn test_batch_request_seeded_rng() {
+    let expiration = 10_000;
+    let (ledger_info_with_signatures, validator_verifier) =
+        create_ledger_info_with_timestamp(expiration - 1);
+    let validator_verifier = Arc::new(validator_verifier);
+
+    let peers: BTreeSet<_> = (0..10).map(|_| AccountAddress::random()).collect();
+    let mut requested_peer_sequences = vec![];
+    for _ in 0..2 {
+        let network_sender =
+            MockBatchRequester::new(BatchResponse::NotFound(ledger_info_with_signatures.clone()));
+        let batch_requester =
+            batch_requester_for_test(1, 3, 10, network_sender.clone(), validator_verifier.clone())
+                .with_rng(QuorumStoreRng::new_with_seed(42));
+
+        let (_, subscriber_rx) = oneshot::channel();
+        let result = batch_requester
+            .request_batch(
+                HashValue::random(),
+                expiration,
+                Arc::new(Mutex::new(peers.clone())),
+                subscriber_rx,
+            )
+            .await;
+        assert_err!(result);
+        requested_peer_sequences.push(network_sender.requested_peers());
+    }
+
+    // Identically seeded requesters pick the same peers
+    assert_eq!(requested_peer_sequences[0].len(), 3);
+    assert_eq!(requested_peer_sequences[0], requested_peer_sequences[1]);
+}
+
+#[tokio::test]
+async fn test_batch_request_num_peers_exceeds_signers() {
+    let expiration = 10_000;
+
+    // Batch is never found and has not expired, so all retries are used
+    let (ledger_info_with_signatures, validator_verifier) =
+        create_ledger_info_with_timestamp(expiration - 1);
+    let network_sender =
+        MockBatchRequester::new(BatchResponse::NotFound(ledger_info_with_signatures));
+    let batch_requester =
+        batch_requester_for_test(5, 2, 100, network_sender.clone(), validator_verifier.into());
+
+    let signers = btreeset![AccountAddress::random(), AccountAddress::random()];
+    let (_, subscriber_rx) = oneshot::channel();
+    let result = batch_requester
+        .request_batch(
+            HashValue::random(),
+            expiration,
+            Arc::new(Mutex::new(signers.clone())),
+            subscriber_rx,
+        )
+        .await;
+    assert_err!(result);
+
+    // Each retry requests every signer exactly once
+    let requested_peers = network_sender.requested_peers();
+    assert_eq!(requested_peers.len(), 4);
+    for retry_peers in requested_peers.chunks(2) {
+        assert_eq!(
+            retry_peers.iter().cloned().collect::<BTreeSet<_>>(),
+            signers
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_batch_request_all_signers_on_last_retry() {
+    let expiration = 10_000;
+
+    // Batch is never found and has not expired, so all retries are used
+    let (ledger_info_with_signatures, validator_verifier) =
+        create_ledger_info_with_timestamp(expiration - 1);
+    let network_sender =
+        MockBatchRequester::new(BatchResponse::NotFound(ledger_info_with_signatures));
+    let batch_requester =
+        batch_requester_for_test(1, 2, 100, network_sender.clone(), validator_verifier.into())
+            .with_all_signers_on_last_retry(true);
+
+    let signers: BTreeSet<_> = (0..4).map(|_| AccountAddress::random()).collect();
+    let (_, subscriber_rx) = oneshot::channel();
+    let result = batch_requester
+        .request_batch(
+            HashValue::random(),
+            expiration,
+            Arc::new(Mutex::new(signers.clone())),
+            subscriber_rx,
+        )
+        .await;
+    assert_err!(result);
+
+    // A single peer on the first try, then all the signers on the last retry
+    let requested_peers = network_sender.requested_peers();
+    assert_eq!(requested_peers.len(), 1 + signers.len());
+    let last_retry_peers: BTreeSet<_> = requested_peers[1..].iter().cloned().collect();
+    assert_eq!(last_retry_peers, signers);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_batch_request_shutdown() {
+    let expiration = 10_000;
+
+    // Batch is never found and has not expired, so requests stay outstanding
+    let (ledger_info_with_signatures, validator_verifier) =
+        create_ledger_info_with_timestamp(expiration - 1);
+    let batch_requester = Arc::new(batch_requester_for_test(
+        1,
+        10,
+        1_000,
+        MockBatchRequester::new(BatchResponse::NotFound(ledger_info_with_signatures)),
+        validator_verifier.into(),
+    ));
+
+    let mut join_handles = vec![];
+    let mut subscriber_txs = vec![];
+    for _ in 0..3 {
+        let requester = batch_requester.clone();
+        let (subscriber_tx, subscriber_rx) = oneshot::channel();
+        subscriber_txs.push(subscriber_tx);
+        join_handles.push(tokio::spawn(async move {
+            requester
+                .request_batch(
+                    HashValue::random(),
+                    expiration,
+                    Arc::new(Mutex::new(btreeset![AccountAddress::random()])),
+                    subscriber_rx,
+                )
+                .await
+        }));
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    batch_requester.shutdown();
+    for join_handle in join_handles {
+        assert_err_eq!(
+            join_handle.await.unwrap(),
+            BatchRequestFailure::ShuttingDown
+        );
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_batch_request_unresponsive_peer() {
+    let validator_signer = ValidatorSigner::random(None);
+    let batch_requester = batch_requester_for_test(
+        1,
+        5,
+        1_000,
+        MockBatchRequester::new_failing(),
+        ValidatorVerifier::new_single(validator_signer.author(), validator_signer.public_key())
+            .into(),
+    )
+    .with_peer_failure_threshold(3);
+
+    let peer = AccountAddress::random();
+    let (_, subscriber_rx) = oneshot::channel();
+    let result = batch_requester
+        .request_batch(
+            HashValue::random(),
+            0,
+            Arc::new(Mutex::new(btreeset![peer])),
+            subscriber_rx,
+        )
+        .await;
+    assert_err!(result);
+    // The only signer never answers, so it is reported once past the threshold
+    assert_eq!(batch_requester.unresponsive_peers(), vec![peer]);
+}
+
+async fn request_batch(
+    batch_requester: &BatchRequester<MockBatchRequester>,
+    batch: &Batch,
+    signers: BTreeSet<Author>,
+) -> Result<(Vec<SignedTransaction>, Option<Author>), BatchRequestFailure> {
+    let (_, subscriber_rx) = oneshot::channel();
+    batch_requester
+        .request_batch(
+            *batch.digest(),
+            batch.expiration(),
+            Arc::new(Mutex::new(signers)),
+            subscriber_rx,
+        )
+        .await
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_batch_request_peer_cooldown() {
+    let cooldown = Duration::from_millis(200);
+
+    let txns = create_vec_signed_transactions(1);
+    let batch = Batch::new(
+        BatchId::new_for_test(1),
+        txns.clone(),
+        1,
+        1,
+        AccountAddress::random(),
+        0,
+    );
+    let mut signers = [AccountAddress::random(), AccountAddress::random()];
+    signers.sort();
+    let [failing_peer, good_peer] = signers;
+    let network_sender =
+        MockBatchRequester::new_with_failing_peers(BatchResponse::Batch(batch.clone()), btreeset![
+            failing_peer
+        ]);
+
+    let validator_signer = ValidatorSigner::random(None);
+    let batch_requester = batch_requester_for_test(
+        1,
+        5,
+        1_000,
+        network_sender.clone(),
+        ValidatorVerifier::new_single(validator_signer.author(), validator_signer.public_key())
+            .into(),
+    )
+    .with_peer_failure_threshold(2)
+    .with_deterministic_peer_order()
+    .with_peer_cooldown(cooldown);
+
+    let signers = btreeset![failing_peer, good_peer];
+    let expected_result = (txns, Some(good_peer));
+
+    // The failing peer is requested first until it failed twice in a row
+    for _ in 0..2 {
+        let result = request_batch(&batch_requester, &batch, signers.clone()).await;
+        assert_ok_eq!(result, expected_result.clone());
+    }
+    assert_eq!(network_sender.requested_peers(), vec![
+        failing_peer,
+        good_peer,
+        failing_peer,
+        good_peer
+    ]);
+
+    // It's skipped during the cooldown
+    let result = request_batch(&batch_requester, &batch, signers.clone()).await;
+    assert_ok_eq!(result, expected_result.clone());
+    assert_eq!(network_sender.requested_peers()[4..], [good_peer]);
+
+    // And requested again once the cooldown elapsed
+    tokio::time::sleep(cooldown).await;
+    let result = request_batch(&batch_requester, &batch, signers).await;
+    assert_ok_eq!(result, expected_result);
+    assert_eq!(network_sender.requested_peers()[5..], [
+        failing_peer,
+        good_peer
+    ]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_batch_request_partial_network_failure() {
+    let retry_interval_ms = 1_000;
+
+    let txns = create_vec_signed_transactions(1);
+    let batch = Batch::new(
+        BatchId::new_for_test(1),
+        txns.clone(),
+        1,
+        1,
+        AccountAddress::random(),
+        0,
+    );
+    let good_peer = AccountAddress::random();
+    let failing_peers = btreeset![AccountAddress::random(), AccountAddress::random()];
+    let network_sender = MockBatchRequester::new_with_failing_peers(
+        BatchResponse::Batch(batch.clone()),
+        failing_peers.clone(),
+    );
+
+    let validator_signer = ValidatorSigner::random(None);
+    let batch_requester = batch_requester_for_test(
+        2,
+        10,
+        retry_interval_ms,
+        network_sender.clone(),
+        ValidatorVerifier::new_single(validator_signer.author(), validator_signer.public_key())
+            .into(),
+    );
+
+    let mut signers = failing_peers.clone();
+    signers.insert(good_peer);
+    let request_start = Instant::now();
+    let (_, subscriber_rx) = oneshot::channel();
+    let result = batch_requester
+        .request_batch(
+            *batch.digest(),
+            batch.expiration(),
+            Arc::new(Mutex::new(signers)),
+            subscriber_rx,
+        )
+        .await;
+    let request_duration = request_start.elapsed();
+    assert_ok_eq!(result, (txns, Some(good_peer)));
+    // Failed sends are compensated with other signers without waiting for the retry interval
+    assert!(request_duration < Duration::from_millis(retry_interval_ms as u64));
+    assert!(network_sender.requested_peers().contains(&good_peer));
+}
+
+#[tokio::test]
+async fn test_batch_request_after_shutdown() {
+    let (ledger_info_with_signatures, validator_verifier) = create_ledger_info_with_timestamp(0);
+    let batch_requester = batch_requester_for_test(
+        1,
+        10,
+        1_000,
+        MockBatchRequester::new(BatchResponse::NotFound(ledger_info_with_signatures)),
+        validator_verifier.into(),
+    );
+    batch_requester.shutdown();
+
+    let (_subscriber_tx, subscriber_rx) = oneshot::channel();
+    let mock_batch_requester_result = tokio::time::timeout(
+        Duration::from_millis(100),
+        batch_requester.request_batch(
+            HashValue::random(),
+            10_000,
+            Arc::new(Mutex::new(btreeset![AccountAddress::random()])),
+            subscriber_rx,
+        ),
+    )
+    .await
+    .expect("request made after shutdown should fail immediately");
+    assert_err_eq!(
+        mock_batch_requester_result,
+        BatchRequestFailure::ShuttingDown
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_batch_request_attempts() {
+    let validator_signer = ValidatorSigner::random(None);
+    let network_sender = MockBatchRequester::new_failing();
+    let batch_requester = batch_requester_for_test(
+        1,
+        3,
+        100,
+        network_sender.clone(),
+        ValidatorVerifier::new_single(validator_signer.author(), validator_signer.public_key())
+            .into(),
+    );
+
+    let peers: BTreeSet<_> = (0..4).map(|_| AccountAddress::random()).collect();
+    let (_, subscriber_rx) = oneshot::channel();
+    assert_err_eq!(
+        batch_requester
+            .request_batch(
+                HashValue::random(),
+                10_000,
+                Arc::new(Mutex::new(peers.clone())),
+                subscriber_rx,
+            )
+            .await,
+        BatchRequestFailure::Unreachable
+    );
+    // Failed peers are replaced within the same retry, so each of the 3 retries requested all
+    // the peers
+    let requested_peers = network_sender.requested_peers();
+    assert_eq!(requested_peers.len(), 3 * peers.len());
+    assert_eq!(requested_peers.into_iter().collect::<BTreeSet<_>>(), peers);
+}
+
+#[tokio::test]
+async fn test_batch_request_already_persisted() {
+    let txns = create_vec_signed_transactions(1);
+    let batch = Batch::new(
+        BatchId::new_for_test(1),
+        txns.clone(),
+        1,
+        1,
+        AccountAddress::random(),
+        0,
+    );
+
+    let validator_signer = ValidatorSigner::random(None);
+    let network_sender = MockBatchRequester::new_failing();
+    let batch_requester = batch_requester_for_test(
+        1,
+        2,
+        1_000,
+        network_sender.clone(),
+        ValidatorVerifier::new_single(validator_signer.author(), validator_signer.public_key())
+            .into(),
+    );
+
+    // The batch was persisted locally before the request started
+    let (subscriber_tx, subscriber_rx) = oneshot::channel();
+    subscriber_tx.send(batch.clone().into()).unwrap();
+    let result = batch_requester
+        .request_batch(
+            *batch.digest(),
+            batch.expiration(),
+            Arc::new(Mutex::new(btreeset![AccountAddress::random()])),
+            subscriber_rx,
+        )
+        .await;
+    assert_ok_eq!(result, (txns, None));
+    assert!(network_sender.requested_peers().is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_batch_request_failure_reasons() {
+    let expiration = 10_000;
+    let (ledger_info_with_signatures, validator_verifier) =
+        create_ledger_info_with_timestamp(expiration - 1);
+    let validator_verifier: Arc<ValidatorVerifier> = validator_verifier.into();
+    let new_batch_requester = |network_sender| {
+        batch_requester_for_test(1, 2, 10, network_sender, validator_verifier.clone())
+    };
+    let cases = [
+        (
+            MockBatchRequester::new_failing(),
+            btreeset![AccountAddress::random()],
+            BatchRequestFailure::Unreachable,
+        ),
+        (
+            MockBatchRequester::new(BatchResponse::NotFound(ledger_info_with_signatures)),
+            btreeset![AccountAddress::random()],
+            BatchRequestFailure::NotFound,
+        ),
+        (
+            MockBatchRequester::new_failing(),
+            BTreeSet::new(),
+            BatchRequestFailure::NoSigners,
+        ),
+    ];
+
+    for (network_sender, signers, failure) in cases {
+        let (_, subscriber_rx) = oneshot::channel();
+        let result = new_batch_requester(network_sender)
+            .request_batch(
+                HashValue::random(),
+                expiration,
+                Arc::new(Mutex::new(signers)),
+                subscriber_rx,
+            )
+            .await;
+        assert_err_eq!(result, failure);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_batch_request_max_total_duration() {
+    let retry_interval_ms = 1_000;
+    let retry_limit = 10;
+    let max_total_duration = Duration::from_millis(100);
+    let expiration = 10_000;
+
+    // The only signer never has the batch, so it's retried on every retry interval
+    let (ledger_info_with_signatures, validator_verifier) =
+        create_ledger_info_with_timestamp(expiration - 1);
+    let network_sender =
+        MockBatchRequester::new(BatchResponse::NotFound(ledger_info_with_signatures));
+    let batch_requester = batch_requester_for_test(
+        1,
+        retry_limit,
+        retry_interval_ms,
+        network_sender.clone(),
+        validator_verifier.into(),
+    )
+    .with_max_total_duration(max_total_duration);
+
+    let request_start = Instant::now();
+    let (_, subscriber_rx) = oneshot::channel();
+    let result = batch_requester
+        .request_batch(
+            HashValue::random(),
+            expiration,
+            Arc::new(Mutex::new(btreeset![AccountAddress::random()])),
+            subscriber_rx,
+        )
+        .await;
+    let request_duration = request_start.elapsed();
+    assert_err_eq!(result, BatchRequestFailure::DeadlineExceeded);
+    // Failed by the time budget, long before the retries are exhausted
+    assert!(request_duration >= max_total_duration);
+    assert!(request_duration < Duration::from_millis(retry_interval_ms as u64));
+    assert!(network_sender.requested_peers().len() < retry_limit);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_batch_request_rate_limit() {
+    let txns = create_vec_signed_transactions(1);
+    let batch = Batch::new(
+        BatchId::new_for_test(1),
+        txns.clone(),
+        1,
+        1,
+        AccountAddress::random(),
+        0,
+    );
+    // Only the last signer in PeerId order serves the batch
+    let mut signers: Vec<_> = (0..4).map(|_| AccountAddress::random()).collect();
+    signers.sort();
+    let good_peer = signers[3];
+    let network_sender = MockBatchRequester::new_with_failing_peers(
+        BatchResponse::Batch(batch.clone()),
+        signers[..3].iter().cloned().collect(),
+    );
+
+    let validator_signer = ValidatorSigner::random(None);
+    let batch_requester = batch_requester_for_test(
+        4,
+        1,
+        1_000,
+        network_sender.clone(),
+        ValidatorVerifier::new_single(validator_signer.author(), validator_signer.public_key())
+            .into(),
+    )
+    .with_deterministic_peer_order()
+    .with_request_rate_limit(2, 2);
+
+    let request_start = Instant::now();
+    let result = request_batch(&batch_requester, &batch, signers.into_iter().collect()).await;
+    assert_ok_eq!(result, (txns, Some(good_peer)));
+    // The four requests are sent at once, but only two fit in the burst and the other two wait
+    // for a refill every half a second
+    assert_eq!(network_sender.requested_peers().len(), 4);
+    assert!(request_start.elapsed() >= Duration::from_secs(1));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_batch_request_health() {
+    let expiration = 10_000;
+
+    // Batch is never found and has not expired, so requests retry every retry interval
+    let (ledger_info_with_signatures, validator_verifier) =
+        create_ledger_info_with_timestamp(expiration - 1);
+    let batch_requester = Arc::new(batch_requester_for_test(
+        1,
+        2,
+        200,
+        MockBatchRequester::new(BatchResponse::NotFound(ledger_info_with_signatures)),
+        validator_verifier.into(),
+    ));
+    assert_eq!(batch_requester.health(), BatchRequesterHealth::default());
+
+    // Both requests are for the same batch, and are still tracked separately
+    let digest = HashValue::random();
+    let request = |batch_requester: Arc<BatchRequester<MockBatchRequester>>| {
+        tokio::spawn(async move {
+            let (_, subscriber_rx) = oneshot::channel();
+            batch_requester
+                .request_batch(
+                    digest,
+                    expiration,
+                    Arc::new(Mutex::new(btreeset![AccountAddress::random()])),
+                    subscriber_rx,
+                )
+                .await
+        })
+    };
+    let request_1 = request(batch_requester.clone());
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let request_2 = request(batch_requester.clone());
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // The first request used both its retries, the second one only its first
+    let health = batch_requester.health();
+    assert_eq!(health.num_pending_requests, 2);
+    assert_eq!(health.total_retries, 3);
+    assert_eq!(health.num_requests_at_retry_limit, 1);
+    assert_eq!(health.oldest_request_age, Some(Duration::from_millis(350)));
+
+    assert_err!(request_1.await.unwrap());
+    assert_err!(request_2.await.unwrap());
+    assert_eq!(batch_requester.health(), BatchRequesterHealth::default());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_batch_request_aborted() {
+    let expiration = 10_000;
+
+    // Batch is never found and has not expired, so the request stays pending until aborted
+    let (ledger_info_with_signatures, validator_verifier) =
+        create_ledger_info_with_timestamp(expiration - 1);
+    let batch_requester = Arc::new(batch_requester_for_test(
+        1,
+        10,
+        1_000,
+        MockBatchRequester::new(BatchResponse::NotFound(ledger_info_with_signatures)),
+        validator_verifier.into(),
+    ));
+
+    let requester = batch_requester.clone();
+    let (fetch, abort_handle) = abortable(async move {
+        let (_, subscriber_rx) = oneshot::channel();
+        requester
+            .request_batch(
+                HashValue::random(),
+                expiration,
+                Arc::new(Mutex::new(btreeset![AccountAddress::random()])),
+                subscriber_rx,
+            )
+            .await
+    });
+    let fetch = tokio::spawn(fetch);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(batch_requester.health().num_pending_requests, 1);
+
+    // Dropping the request future, as cancel_expired_fetches does, removes the pending request
+    abort_handle.abort();
+    assert_err!(fetch.await.unwrap());
+    assert_eq!(batch_requester.health(), BatchRequesterHealth::default());
+}
+
+#[tokio::test]
+async fn test_batch_request_too_large() {
+    let txns = create_vec_signed_transactions(5);
+    let batch = Batch::new(
+        BatchId::new_for_test(1),
+        txns.clone(),
+        1,
+        1,
+        AccountAddress::random(),
+        0,
+    );
+    let num_bytes: u64 = txns.iter().map(|txn| txn.txn_bytes_len() as u64).sum();
+
+    let validator_signer = ValidatorSigner::random(None);
+    let network_sender = MockBatchRequester::new(BatchResponse::Batch(batch.clone()));
+    let batch_requester = batch_requester_for_test(
+        1,
+        2,
+        100,
+        network_sender.clone(),
+        ValidatorVerifier::new_single(validator_signer.author(), validator_signer.public_key())
+            .into(),
+    )
+    .with_max_batch_bytes(num_bytes - 1);
+
+    let (_, subscriber_rx) = oneshot::channel();
+    let result = batch_requester
+        .request_batch(
+            *batch.digest(),
+            batch.expiration(),
+            Arc::new(Mutex::new(btreeset![AccountAddress::random()])),
+            subscriber_rx,
+        )
+        .await;
+    // The oversized batch is dropped on every retry
+    assert_err_eq!(result, BatchRequestFailure::Unreachable);
+    assert_eq!(network_sender.requested_peers().len(), 2);
+}
+
+#[tokio::test]
+async fn test_batch_request_wrong_epoch() {
+    // Same txns and digest as the requested batch, but batched in epoch 2
+    let batch = Batch::new(
+        BatchId::new_for_test(1),
+        create_vec_signed_transactions(5),
+        2,
+        1,
+        AccountAddress::random(),
+        0,
+    );
+
+    let validator_signer = ValidatorSigner::random(None);
+    let network_sender = MockBatchRequester::new(BatchResponse::Batch(batch.clone()));
+    let batch_requester = batch_requester_for_test(
+        1,
+        2,
+        100,
+        network_sender.clone(),
+        ValidatorVerifier::new_single(validator_signer.author(), validator_signer.public_key())
+            .into(),
+    );
+
+    let (_, subscriber_rx) = oneshot::channel();
+    let result = batch_requester
+        .request_batch(
+            *batch.digest(),
+            batch.expiration(),
+            Arc::new(Mutex::new(btreeset![AccountAddress::random()])),
+            subscriber_rx,
+        )
+        .await;
+    // The batch from the other epoch is ignored on every retry
+    assert_err_eq!(result, BatchRequestFailure::Unreachable);
+    assert_eq!(network_sender.requested_peers().len(), 2);
+}
@@ -1,25 +1,44 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::quorum_store::{
-    batch_store::{BatchStore, BatchWriter, QuotaManager},
-    quorum_store_db::QuorumStoreDB,
-    types::{PersistedValue, StorageMode},
+use crate::{
+    network::QuorumStoreSender,
+    quorum_store::{
+        batch_requester::{BatchRequestFailure, BatchRetriever},
+        batch_store::{
+            BatchReader, BatchReaderImpl, BatchRequestPriority, BatchStore, BatchWriter,
+            QuotaManager,
+        },
+        counters,
+        quorum_store_db::QuorumStoreDB,
+        tests::batch_requester_test::batch_requester_for_test,
+        types::{Batch, BatchRequest, BatchResponse, PersistedValue, StorageMode},
+    },
+    test_utils::create_vec_signed_transactions,
+};
+use aptos_consensus_types::{
+    common::Author,
+    proof_of_store::{BatchId, BatchInfo, ProofOfStore, SignedBatchInfo},
 };
-use aptos_consensus_types::proof_of_store::{BatchId, BatchInfo};
 use aptos_crypto::HashValue;
+use aptos_executor_types::ExecutorError;
+use aptos_infallible::Mutex;
 use aptos_temppath::TempPath;
 use aptos_types::{
     account_address::AccountAddress, transaction::SignedTransaction,
-    validator_verifier::random_validator_verifier,
+    validator_verifier::random_validator_verifier, PeerId,
 };
-use claims::{assert_err, assert_ok, assert_ok_eq};
+use claims::{assert_err, assert_err_eq, assert_ge, assert_ok, assert_ok_eq};
 use once_cell::sync::Lazy;
-use std::sync::{
-    atomic::{AtomicBool, AtomicUsize, Ordering},
-    Arc,
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
-use tokio::task::spawn_blocking;
+use tokio::{sync::oneshot, task::spawn_blocking};
 
 static TEST_REQUEST_ACCOUNT: Lazy<AccountAddress> = Lazy::new(AccountAddress::random);
 
@@ -283,3 +302,434 @@ async fn test_get_local_batch() {
     assert_err!(store.get_batch_from_local(&digest_2));
     assert_err!(store.get_batch_from_local(&digest_3));
 }
+
+#[derive(Clone)]
+struct UnresponsiveBatchRequester {
+    requested_digests: Arc<Mutex<Vec<HashValue>>>,
+}
+
+impl UnresponsiveBatchRequester {
+    fn new() -> Self {
+        Self {
+            requested_digests: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    fn requested_digests(&self) -> Vec<HashValue> {
+        self.requested_digests.lock().clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl QuorumStoreSender for UnresponsiveBatchRequester {
+    async fn request_batch(
+        &self,
+        request: BatchRequest,
+        _recipient: Author,
+        _timeout: Duration,
+    ) -> anyhow::Result<BatchResponse> {
+        self.requested_digests.lock().push(request.digest());
+        futures::future::pending().await
+    }
+
+    async fn send_signed_batch_info_msg(
+        &self,
+        _signed_batch_infos: Vec<SignedBatchInfo>,
+        _recipients: Vec<Author>,
+    ) {
+        unimplemented!()
+    }
+
+    async fn broadcast_batch_msg(&mut self, _batches: Vec<Batch>) {
+        unimplemented!()
+    }
+
+    async fn broadcast_proof_of_store_msg(&mut self, _proof_of_stores: Vec<ProofOfStore>) {
+        unimplemented!()
+    }
+
+    async fn send_proof_of_store_msg_to_self(&mut self, _proof_of_stores: Vec<ProofOfStore>) {
+        unimplemented!()
+    }
+}
+
+#[tokio::test]
+async fn test_in_flight_bytes() {
+    let store = batch_store_for_test(100);
+    let (_signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let batch_reader = BatchReaderImpl::new(
+        store.clone(),
+        batch_requester_for_test(
+            1,
+            10,
+            1_000,
+            UnresponsiveBatchRequester::new(),
+            validator_verifier.into(),
+        ),
+        1_000,
+    );
+    assert_eq!(batch_reader.in_flight_bytes(), 0);
+
+    let request_1 = request_for_test(&HashValue::random(), 50, 20, Some(vec![]));
+    let request_2 = request_for_test(&HashValue::random(), 50, 30, Some(vec![]));
+    let fut_1 = batch_reader.get_batch(
+        request_1.batch_info().clone(),
+        vec![AccountAddress::random()],
+        BatchRequestPriority::High,
+    );
+    let _fut_2 = batch_reader.get_batch(
+        request_2.batch_info().clone(),
+        vec![AccountAddress::random()],
+        BatchRequestPriority::High,
+    );
+    assert_eq!(batch_reader.in_flight_bytes(), 50);
+
+    // Once the first batch arrives, only the second one is in flight
+    assert!(!store.persist(vec![request_1]).is_empty());
+    assert_ok!(fut_1.await);
+    assert_eq!(batch_reader.in_flight_bytes(), 30);
+}
+
+#[tokio::test]
+async fn test_get_batch_served_from_memory() {
+    let store = batch_store_for_test(100);
+    let (_signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let network_sender = UnresponsiveBatchRequester::new();
+    let batch_reader = BatchReaderImpl::new(
+        store.clone(),
+        batch_requester_for_test(
+            1,
+            10,
+            1_000,
+            network_sender.clone(),
+            validator_verifier.into(),
+        ),
+        1_000,
+    );
+
+    let txns = create_vec_signed_transactions(2);
+    let request = request_for_test(&HashValue::random(), 50, 20, Some(txns.clone()));
+    assert!(!store.persist(vec![request.clone()]).is_empty());
+
+    let fut = batch_reader.get_batch(
+        request.batch_info().clone(),
+        vec![AccountAddress::random()],
+        BatchRequestPriority::High,
+    );
+    // A locally available batch is served without an in-flight fetch
+    assert_eq!(batch_reader.in_flight_bytes(), 0);
+    assert_ok_eq!(fut.await, txns);
+    assert!(network_sender.requested_digests().is_empty());
+}
+
+#[tokio::test]
+async fn test_get_batch_served_from_db() {
+    // The batch exceeds the memory quota, so its payload is only in the db
+    let store = batch_store_for_test(10);
+    let (_signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let network_sender = UnresponsiveBatchRequester::new();
+    let batch_reader = BatchReaderImpl::new(
+        store.clone(),
+        batch_requester_for_test(
+            1,
+            10,
+            1_000,
+            network_sender.clone(),
+            validator_verifier.into(),
+        ),
+        1_000,
+    );
+
+    let txns = create_vec_signed_transactions(2);
+    let request = request_for_test(&HashValue::random(), 50, 20, Some(txns.clone()));
+    assert!(!store.persist(vec![request.clone()]).is_empty());
+
+    let fut = batch_reader.get_batch(
+        request.batch_info().clone(),
+        vec![AccountAddress::random()],
+        BatchRequestPriority::High,
+    );
+    // The db is read by the fetch rather than by the caller, and no peer is requested
+    assert_eq!(batch_reader.in_flight_bytes(), 20);
+    assert_ok_eq!(fut.await, txns);
+    assert!(network_sender.requested_digests().is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_max_outstanding_bytes() {
+    let store = batch_store_for_test(100);
+    let (_signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let network_sender = UnresponsiveBatchRequester::new();
+    let batch_reader = BatchReaderImpl::new(
+        store.clone(),
+        batch_requester_for_test(
+            1,
+            10,
+            1_000,
+            network_sender.clone(),
+            validator_verifier.into(),
+        ),
+        40,
+    );
+
+    let request_1 = request_for_test(&HashValue::random(), 50, 30, Some(vec![]));
+    let request_2 = request_for_test(&HashValue::random(), 50, 20, Some(vec![]));
+    let fut_1 = batch_reader.get_batch(
+        request_1.batch_info().clone(),
+        vec![AccountAddress::random()],
+        BatchRequestPriority::Low,
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(
+        network_sender.requested_digests(),
+        vec![*request_1.digest()]
+    );
+
+    // Exceeds the budget, so it's deferred until the first batch arrives
+    let _fut_2 = batch_reader.get_batch(
+        request_2.batch_info().clone(),
+        vec![AccountAddress::random()],
+        BatchRequestPriority::Low,
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(
+        network_sender.requested_digests(),
+        vec![*request_1.digest()]
+    );
+
+    assert!(!store.persist(vec![request_1.clone()]).is_empty());
+    assert_ok!(fut_1.await);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(network_sender.requested_digests(), vec![
+        *request_1.digest(),
+        *request_2.digest()
+    ]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_outstanding_bytes_saturation() {
+    let store = batch_store_for_test(100);
+    let (_signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let batch_reader = BatchReaderImpl::new(
+        store.clone(),
+        batch_requester_for_test(
+            1,
+            10,
+            1_000,
+            UnresponsiveBatchRequester::new(),
+            validator_verifier.into(),
+        ),
+        40,
+    );
+    assert_eq!(batch_reader.capacity_remaining(), 40);
+    assert!(!batch_reader.is_saturated());
+
+    let request = request_for_test(&HashValue::random(), 50, 40, Some(vec![]));
+    let fut = batch_reader.get_batch(
+        request.batch_info().clone(),
+        vec![AccountAddress::random()],
+        BatchRequestPriority::Low,
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(batch_reader.capacity_remaining(), 0);
+    assert!(batch_reader.is_saturated());
+
+    assert!(!store.persist(vec![request]).is_empty());
+    assert_ok!(fut.await);
+    assert_eq!(batch_reader.capacity_remaining(), 40);
+    assert!(!batch_reader.is_saturated());
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_outstanding_bytes_saturated_count() {
+    let store = batch_store_for_test(100);
+    let (_signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let batch_reader = BatchReaderImpl::new(
+        store.clone(),
+        batch_requester_for_test(
+            1,
+            10,
+            1_000,
+            UnresponsiveBatchRequester::new(),
+            validator_verifier.into(),
+        ),
+        40,
+    );
+    let get_batch = |num_bytes| {
+        let request = request_for_test(&HashValue::random(), 50, num_bytes, Some(vec![]));
+        batch_reader.get_batch(
+            request.batch_info().clone(),
+            vec![AccountAddress::random()],
+            BatchRequestPriority::Low,
+        )
+    };
+
+    let _fut_1 = get_batch(40);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(batch_reader.is_saturated());
+
+    // Other tests may fetch concurrently, so only check that this fetch was counted
+    let saturated_count = counters::BATCH_REQUEST_BUDGET_SATURATED_COUNT.get();
+    let _fut_2 = get_batch(10);
+    assert_ge!(
+        counters::BATCH_REQUEST_BUDGET_SATURATED_COUNT.get(),
+        saturated_count + 1
+    );
+}
+
+/// Serves batches from a fixed set, instead of requesting them from peers.
+struct StaticBatchRetriever {
+    batches: HashMap<HashValue, Vec<SignedTransaction>>,
+}
+
+#[async_trait::async_trait]
+impl BatchRetriever for StaticBatchRetriever {
+    async fn request_batch(
+        &self,
+        digest: HashValue,
+        _expiration: u64,
+        _responders: Arc<Mutex<BTreeSet<PeerId>>>,
+        _subscriber_rx: oneshot::Receiver<PersistedValue>,
+    ) -> Result<(Vec<SignedTransaction>, Option<PeerId>), BatchRequestFailure> {
+        self.batches
+            .get(&digest)
+            .map(|txns| (txns.clone(), None))
+            .ok_or(BatchRequestFailure::NotFound)
+    }
+
+    fn shutdown(&self) {}
+}
+
+#[tokio::test]
+async fn test_alternative_batch_retriever() {
+    let store = batch_store_for_test(100);
+    let txns = create_vec_signed_transactions(2);
+    let request_1 = request_for_test(&HashValue::random(), 50, 20, None);
+    let request_2 = request_for_test(&HashValue::random(), 50, 20, None);
+    let batch_reader = BatchReaderImpl::new(
+        store.clone(),
+        StaticBatchRetriever {
+            batches: HashMap::from([(*request_1.digest(), txns.clone())]),
+        },
+        1_000,
+    );
+
+    assert_ok_eq!(
+        batch_reader
+            .get_batch(
+                request_1.batch_info().clone(),
+                vec![],
+                BatchRequestPriority::High,
+            )
+            .await,
+        txns
+    );
+    // The retrieved batch is persisted to the batch store
+    assert_ok!(store.get_batch_from_local(request_1.digest()));
+
+    assert_err_eq!(
+        batch_reader
+            .get_batch(
+                request_2.batch_info().clone(),
+                vec![],
+                BatchRequestPriority::High,
+            )
+            .await,
+        ExecutorError::CouldNotGetData
+    );
+}
+
+#[tokio::test]
+async fn test_cancel_expired_fetches() {
+    let store = batch_store_for_test(100);
+    let (_signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let batch_reader = BatchReaderImpl::new(
+        store.clone(),
+        batch_requester_for_test(
+            1,
+            10,
+            1_000,
+            UnresponsiveBatchRequester::new(),
+            validator_verifier.into(),
+        ),
+        1_000,
+    );
+
+    let request_1 = request_for_test(&HashValue::random(), 50, 20, None);
+    let request_2 = request_for_test(&HashValue::random(), 100, 30, None);
+    let fut_1 = batch_reader.get_batch(
+        request_1.batch_info().clone(),
+        vec![AccountAddress::random()],
+        BatchRequestPriority::High,
+    );
+    let _fut_2 = batch_reader.get_batch(
+        request_2.batch_info().clone(),
+        vec![AccountAddress::random()],
+        BatchRequestPriority::High,
+    );
+    assert_eq!(batch_reader.in_flight_bytes(), 50);
+
+    // Only the fetch of the expired batch is cancelled
+    batch_reader.update_certified_timestamp(80);
+    assert_err_eq!(fut_1.await, ExecutorError::CouldNotGetData);
+    assert_eq!(batch_reader.in_flight_bytes(), 30);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_batch_request_priority() {
+    let store = batch_store_for_test(100);
+    let (_signers, validator_verifier) = random_validator_verifier(4, None, false);
+    let network_sender = UnresponsiveBatchRequester::new();
+    let batch_reader = BatchReaderImpl::new(
+        store.clone(),
+        batch_requester_for_test(
+            1,
+            10,
+            1_000,
+            network_sender.clone(),
+            validator_verifier.into(),
+        ),
+        40,
+    );
+
+    let request_1 = request_for_test(&HashValue::random(), 50, 30, None);
+    let request_2 = request_for_test(&HashValue::random(), 50, 20, None);
+    let request_3 = request_for_test(&HashValue::random(), 50, 20, None);
+    for request in [&request_1, &request_2] {
+        let _ = batch_reader.get_batch(
+            request.batch_info().clone(),
+            vec![AccountAddress::random()],
+            BatchRequestPriority::Low,
+        );
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(
+        network_sender.requested_digests(),
+        vec![*request_1.digest()]
+    );
+
+    // A high priority request doesn't wait behind the queued low priority one
+    let _ = batch_reader.get_batch(
+        request_3.batch_info().clone(),
+        vec![AccountAddress::random()],
+        BatchRequestPriority::High,
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(network_sender.requested_digests(), vec![
+        *request_1.digest(),
+        *request_3.digest()
+    ]);
+
+    // Requesting the queued batch with high priority dispatches it too
+    let _ = batch_reader.get_batch(
+        request_2.batch_info().clone(),
+        vec![AccountAddress::random()],
+        BatchRequestPriority::High,
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(network_sender.requested_digests(), vec![
+        *request_1.digest(),
+        *request_3.digest(),
+        *request_2.digest()
+    ]);
+}
@@ -43,13 +43,7 @@ async fn test_block_request_no_txns() {
         }))
         .unwrap();
 
-    if let QuorumStoreRequest::GetBatchRequest(
-        _max_batch_size,
-        _max_bytes,
-        _return_non_full,
-        _exclude_txns,
-        callback,
-    ) = timeout(
+    if let QuorumStoreRequest::GetBatchRequest { callback, .. } = timeout(
         Duration::from_millis(1_000),
         quorum_store_to_mempool_receiver.select_next_some(),
     )
@@ -1,6 +1,7 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
+mod batch_fetch_flow_test;
 mod batch_generator_test;
 mod batch_proof_queue_test;
 mod batch_requester_test;
@@ -10,3 +11,4 @@ mod proof_coordinator_test;
 mod proof_manager_test;
 mod quorum_store_db_test;
 mod types_test;
+mod utils_test;
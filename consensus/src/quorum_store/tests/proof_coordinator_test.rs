@@ -4,7 +4,7 @@
 use crate::{
     network_interface::ConsensusMsg,
     quorum_store::{
-        batch_store::BatchReader,
+        batch_store::{BatchReader, BatchRequestPriority},
         proof_coordinator::{ProofCoordinator, ProofCoordinatorCommand},
         types::Batch,
     },
@@ -36,6 +36,7 @@ impl BatchReader for MockBatchReader {
         &self,
         _batch_info: BatchInfo,
         _signers: Vec<PeerId>,
+        _priority: BatchRequestPriority,
     ) -> Shared<Pin<Box<dyn Future<Output = ExecutorResult<Vec<SignedTransaction>>> + Send>>> {
         unimplemented!()
     }
@@ -43,6 +44,10 @@ impl BatchReader for MockBatchReader {
     fn update_certified_timestamp(&self, _certified_time: u64) {
         unimplemented!()
     }
+
+    fn shutdown(&self) {
+        unimplemented!()
+    }
 }
 
 #[tokio::test(flavor = "multi_thread")]
@@ -0,0 +1,236 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    quorum_store::{
+        counters,
+        utils::{MempoolProxy, MempoolPullError, RollingAverage, Timeouts},
+    },
+    test_utils::create_vec_signed_transactions,
+};
+use aptos_mempool::{QuorumStoreRequest, QuorumStoreResponse};
+use aptos_time_service::TimeService;
+use claims::assert_ge;
+use futures::{channel::mpsc::channel, StreamExt};
+use std::{collections::BTreeMap, time::Duration};
+
+#[test]
+fn test_timeouts_values() {
+    let mut timeouts = Timeouts::new();
+    timeouts.add(1, 0);
+    timeouts.add(2, 60_000);
+    timeouts.add(3, 30_000);
+    assert_eq!(timeouts.values().cloned().collect::<Vec<_>>(), vec![
+        1, 3, 2
+    ]);
+
+    assert_eq!(timeouts.expire(), vec![1]);
+    assert_eq!(timeouts.values().cloned().collect::<Vec<_>>(), vec![3, 2]);
+}
+
+#[test]
+fn test_timeouts_mock_time_service() {
+    let time_service = TimeService::mock();
+    let mut timeouts = Timeouts::new_with_time_service(time_service.clone());
+    let mock_time_service = time_service.into_mock();
+    timeouts.add(1, 30_000);
+    timeouts.add(2, 60_000);
+    assert!(timeouts.expire().is_empty());
+
+    mock_time_service.advance(Duration::from_secs(30));
+    assert_eq!(timeouts.expire(), vec![1]);
+
+    mock_time_service.advance(Duration::from_secs(30));
+    assert_eq!(timeouts.expire(), vec![2]);
+}
+
+#[test]
+fn test_timeouts_duplicate() {
+    let time_service = TimeService::mock();
+    let mut timeouts = Timeouts::new_with_time_service(time_service.clone());
+    timeouts.add(1, 10_000);
+    // Already tracked, so the original timeout is kept
+    timeouts.add(1, 60_000);
+    assert_eq!(timeouts.values().cloned().collect::<Vec<_>>(), vec![1]);
+
+    time_service.into_mock().advance(Duration::from_secs(60));
+    assert_eq!(timeouts.expire(), vec![1]);
+    assert!(timeouts.expire().is_empty());
+
+    // Once expired, it can be tracked again
+    timeouts.add(1, 0);
+    assert_eq!(timeouts.expire(), vec![1]);
+}
+
+#[test]
+fn test_rolling_average() {
+    let mut rolling_average = RollingAverage::new(3);
+    assert_eq!(rolling_average.average(), None);
+
+    rolling_average.add(0.5);
+    assert_eq!(rolling_average.average(), Some(0.5));
+    rolling_average.add(0.25);
+    rolling_average.add(0.75);
+    assert_eq!(rolling_average.average(), Some(0.5));
+
+    // The first value leaves the window
+    rolling_average.add(1.0);
+    assert_eq!(rolling_average.average(), Some(2.0 / 3.0));
+}
+
+#[tokio::test]
+async fn test_mempool_proxy_exceeded_max_bytes() {
+    let (mempool_tx, mut mempool_rx) = channel(10);
+    let mempool_proxy = MempoolProxy::new(mempool_tx, 1_000);
+
+    let txns = create_vec_signed_transactions(3);
+    let txn_bytes = txns[0].txn_bytes_len() as u64;
+    let mempool_txns = txns.clone();
+    tokio::spawn(async move {
+        while let Some(QuorumStoreRequest::GetBatchRequest { callback, .. }) =
+            mempool_rx.next().await
+        {
+            // buggy mempool ignores max_bytes
+            callback
+                .send(Ok(QuorumStoreResponse::GetBatchResponse(
+                    mempool_txns.clone(),
+                )))
+                .unwrap();
+        }
+    });
+
+    assert_eq!(
+        mempool_proxy
+            .pull_internal(10, 3 * txn_bytes, BTreeMap::new())
+            .await
+            .unwrap(),
+        txns
+    );
+    assert!(matches!(
+        mempool_proxy
+            .pull_internal(10, 2 * txn_bytes, BTreeMap::new())
+            .await,
+        Err(MempoolPullError::ExceededMaxBytes { max_bytes, num_bytes })
+            if max_bytes == 2 * txn_bytes && num_bytes == 3 * txn_bytes
+    ));
+}
+
+#[tokio::test]
+async fn test_mempool_proxy_channel_full() {
+    let (mempool_tx, _mempool_rx) = channel(0);
+    let mempool_proxy = MempoolProxy::new(mempool_tx, 100);
+
+    // Nothing receives the first request, so it fills up the channel and times out
+    assert!(matches!(
+        mempool_proxy
+            .pull_internal(10, 1024 * 1024, BTreeMap::new())
+            .await,
+        Err(MempoolPullError::Timeout)
+    ));
+    assert!(matches!(
+        mempool_proxy
+            .pull_internal(10, 1024 * 1024, BTreeMap::new())
+            .await,
+        Err(MempoolPullError::ChannelFull)
+    ));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_mempool_proxy_pull_min_items() {
+    let (mempool_tx, mut mempool_rx) = channel(10);
+    let mempool_proxy = MempoolProxy::new(mempool_tx, 100);
+
+    let txns = create_vec_signed_transactions(5);
+    let mempool_txns = txns.clone();
+    let mempool = tokio::spawn(async move {
+        // Mempool has too few txns at first, and responds once more arrive, after longer than
+        // the pull timeout
+        let mut num_pulls = 0;
+        while let Some(QuorumStoreRequest::GetBatchRequest {
+            max_txns: max_items,
+            min_txns,
+            max_wait,
+            callback,
+            ..
+        }) = mempool_rx.next().await
+        {
+            assert_eq!(max_items, 10);
+            assert_eq!(min_txns, 5);
+            assert_eq!(max_wait, Duration::from_secs(1));
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            callback
+                .send(Ok(QuorumStoreResponse::GetBatchResponse(
+                    mempool_txns.clone(),
+                )))
+                .unwrap();
+            num_pulls += 1;
+        }
+        num_pulls
+    });
+
+    let pulled_txns = mempool_proxy
+        .pull_internal_min_items(10, 1024 * 1024, 5, Duration::from_secs(1), BTreeMap::new())
+        .await
+        .unwrap();
+    assert_eq!(pulled_txns, txns);
+    drop(mempool_proxy);
+    // The proxy waited for mempool instead of pulling again
+    assert_eq!(mempool.await.unwrap(), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_mempool_proxy_pull_metrics() {
+    let (mempool_tx, mut mempool_rx) = channel(10);
+    let mempool_proxy = MempoolProxy::new(mempool_tx, 100);
+
+    let txns = create_vec_signed_transactions(2);
+    let mempool_txns = txns.clone();
+    tokio::spawn(async move {
+        // Serves the first pull after a delay, and holds on to the second without serving it
+        let mut unserved_callbacks = vec![];
+        let mut num_pulls = 0;
+        while let Some(QuorumStoreRequest::GetBatchRequest { callback, .. }) =
+            mempool_rx.next().await
+        {
+            num_pulls += 1;
+            if num_pulls == 1 {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                callback
+                    .send(Ok(QuorumStoreResponse::GetBatchResponse(
+                        mempool_txns.clone(),
+                    )))
+                    .unwrap();
+            } else {
+                unserved_callbacks.push(callback);
+            }
+        }
+    });
+
+    let num_pulls = counters::MEMPOOL_PULL_LATENCY.get_sample_count();
+    let latency_sum = counters::MEMPOOL_PULL_LATENCY.get_sample_sum();
+    assert_eq!(
+        mempool_proxy
+            .pull_internal(10, 1024 * 1024, BTreeMap::new())
+            .await
+            .unwrap(),
+        txns
+    );
+    // Other tests may pull concurrently, so only check that the pull was recorded
+    assert_ge!(
+        counters::MEMPOOL_PULL_LATENCY.get_sample_count(),
+        num_pulls + 1
+    );
+    assert_ge!(
+        counters::MEMPOOL_PULL_LATENCY.get_sample_sum(),
+        latency_sum + 0.02
+    );
+
+    let num_timeouts = counters::MEMPOOL_PULL_TIMEOUT_COUNT.get();
+    assert!(matches!(
+        mempool_proxy
+            .pull_internal(10, 1024 * 1024, BTreeMap::new())
+            .await,
+        Err(MempoolPullError::Timeout)
+    ));
+    assert_ge!(counters::MEMPOOL_PULL_TIMEOUT_COUNT.get(), num_timeouts + 1);
+}
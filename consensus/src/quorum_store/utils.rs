@@ -1,44 +1,134 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::monitor;
+use crate::{monitor, quorum_store::counters};
 use aptos_consensus_types::{
     common::{TransactionInProgress, TransactionSummary},
     proof_of_store::{BatchId, BatchInfo},
 };
+use aptos_infallible::Mutex;
 use aptos_logger::prelude::*;
 use aptos_mempool::{QuorumStoreRequest, QuorumStoreResponse};
+use aptos_time_service::{TimeService, TimeServiceTrait};
 use aptos_types::{transaction::SignedTransaction, PeerId};
-use chrono::Utc;
 use futures::channel::{mpsc::Sender, oneshot};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::{
     cmp::{Ordering, Reverse},
     collections::{BTreeMap, BinaryHeap, HashSet, VecDeque},
     hash::Hash,
+    sync::Arc,
     time::Duration,
 };
-use tokio::time::timeout;
+use thiserror::Error;
+use tokio::time::{error::Elapsed, timeout_at, Instant};
+
+/// Randomness for the quorum store, e.g. peer selection. Clones share the same generator.
+/// Components given the same seed make the same random choices, so a failing test can be
+/// replayed exactly.
+#[derive(Clone)]
+pub(crate) struct QuorumStoreRng(Arc<Mutex<StdRng>>);
+
+impl QuorumStoreRng {
+    pub(crate) fn new() -> Self {
+        Self(Arc::new(Mutex::new(StdRng::from_entropy())))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn new_with_seed(seed: u64) -> Self {
+        Self(Arc::new(Mutex::new(StdRng::seed_from_u64(seed))))
+    }
+
+    /// A random number in [low, high).
+    pub(crate) fn gen_range(&self, low: usize, high: usize) -> usize {
+        self.0.lock().gen_range(low, high)
+    }
+}
+
+impl Default for QuorumStoreRng {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Average of the last window_size values added, e.g. to smooth out a per-batch signal.
+#[derive(Clone, Debug)]
+pub(crate) struct RollingAverage {
+    values: VecDeque<f64>,
+    window_size: usize,
+}
+
+impl RollingAverage {
+    pub(crate) fn new(window_size: usize) -> Self {
+        assert!(window_size > 0, "window_size must be positive");
+        Self {
+            values: VecDeque::with_capacity(window_size),
+            window_size,
+        }
+    }
+
+    pub(crate) fn add(&mut self, value: f64) {
+        if self.values.len() == self.window_size {
+            self.values.pop_front();
+        }
+        self.values.push_back(value);
+    }
+
+    /// None until a value is added.
+    pub(crate) fn average(&self) -> Option<f64> {
+        if self.values.is_empty() {
+            None
+        } else {
+            Some(self.values.iter().sum::<f64>() / self.values.len() as f64)
+        }
+    }
+}
 
 pub(crate) struct Timeouts<T> {
     timeouts: VecDeque<(i64, T)>,
+    // the values in timeouts, to ignore values that are already tracked
+    tracked: HashSet<T>,
+    time_service: TimeService,
 }
 
-impl<T> Timeouts<T> {
+impl<T: Clone + Eq + Hash> Timeouts<T> {
     pub(crate) fn new() -> Self {
         Self {
             timeouts: VecDeque::new(),
+            tracked: HashSet::new(),
+            time_service: TimeService::real(),
         }
     }
 
+    /// Reads time from the given time service, e.g. a mock one that tests advance manually.
+    #[cfg(test)]
+    pub(crate) fn new_with_time_service(time_service: TimeService) -> Self {
+        Self {
+            time_service,
+            ..Self::new()
+        }
+    }
+
+    fn now_ms(&self) -> i64 {
+        self.time_service.now_unix_time().as_millis() as i64
+    }
+
+    /// Adding a value that is already tracked is a no-op and keeps its original timeout, so
+    /// each value expires once.
     pub(crate) fn add(&mut self, value: T, timeout: usize) {
-        #[allow(deprecated)]
-        let expiry = Utc::now().naive_utc().timestamp_millis() + timeout as i64;
-        self.timeouts.push_back((expiry, value));
+        if !self.tracked.insert(value.clone()) {
+            return;
+        }
+        let expiry = self.now_ms() + timeout as i64;
+        // keep the timeouts sorted by expiry, as they no longer necessarily arrive in order
+        let index = self
+            .timeouts
+            .partition_point(|(expiration_time, _)| *expiration_time <= expiry);
+        self.timeouts.insert(index, (expiry, value));
     }
 
     pub(crate) fn expire(&mut self) -> Vec<T> {
-        #[allow(deprecated)]
-        let cur_time = Utc::now().naive_utc().timestamp_millis();
+        let cur_time = self.now_ms();
         trace!(
             "QS: expire cur time {} timeouts len {}",
             cur_time,
@@ -50,10 +140,21 @@ impl<T> Timeouts<T> {
             .take_while(|(expiration_time, _)| cur_time >= *expiration_time)
             .count();
 
-        self.timeouts
+        let expired: Vec<T> = self
+            .timeouts
             .drain(0..num_expired)
             .map(|(_, h)| h)
-            .collect()
+            .collect();
+        for value in &expired {
+            self.tracked.remove(value);
+        }
+        expired
+    }
+
+    /// All the values that haven't expired yet, in expiry order.
+    #[cfg(test)]
+    pub(crate) fn values(&self) -> impl Iterator<Item = &T> {
+        self.timeouts.iter().map(|(_, value)| value)
     }
 }
 
@@ -94,15 +195,34 @@ impl<I: Ord + Hash> TimeExpirations<I> {
     }
 }
 
+#[derive(Clone, Debug, Error)]
+pub enum MempoolPullError {
+    #[error("[quorum_store] mempool channel is full")]
+    ChannelFull,
+    #[error("[quorum_store] failed to send GetBatchRequest: {0}")]
+    SendFailed(String),
+    #[error("[quorum_store] did not receive GetBatchResponse on time")]
+    Timeout,
+    #[error("[quorum_store] GetBatchRequest callback was dropped")]
+    Canceled,
+    #[error("[quorum_store] mempool failed to serve GetBatchRequest: {0}")]
+    Mempool(Arc<anyhow::Error>),
+    #[error("[quorum_store] did not receive expected GetBatchResponse")]
+    UnexpectedResponse,
+    #[error("[quorum_store] mempool returned {num_bytes} bytes, more than max {max_bytes} bytes")]
+    ExceededMaxBytes { max_bytes: u64, num_bytes: u64 },
+}
+
 pub struct MempoolProxy {
-    mempool_tx: Sender<QuorumStoreRequest>,
+    // a single sender, as a fresh clone can always send one message even if the channel is full
+    mempool_tx: Mutex<Sender<QuorumStoreRequest>>,
     mempool_txn_pull_timeout_ms: u64,
 }
 
 impl MempoolProxy {
     pub fn new(mempool_tx: Sender<QuorumStoreRequest>, mempool_txn_pull_timeout_ms: u64) -> Self {
         Self {
-            mempool_tx,
+            mempool_tx: Mutex::new(mempool_tx),
             mempool_txn_pull_timeout_ms,
         }
     }
@@ -112,37 +232,121 @@ impl MempoolProxy {
         max_items: u64,
         max_bytes: u64,
         exclude_transactions: BTreeMap<TransactionSummary, TransactionInProgress>,
-    ) -> Result<Vec<SignedTransaction>, anyhow::Error> {
-        let (callback, callback_rcv) = oneshot::channel();
-        let msg = QuorumStoreRequest::GetBatchRequest(
+    ) -> Result<Vec<SignedTransaction>, MempoolPullError> {
+        let callback_rcv = self.send_pull_request(
             max_items,
             max_bytes,
-            true,
             exclude_transactions,
-            callback,
-        );
-        self.mempool_tx
-            .clone()
-            .try_send(msg)
-            .map_err(anyhow::Error::from)?;
+            0,
+            Duration::ZERO,
+        )?;
         // wait for response
-        match monitor!(
+        monitor!(
             "pull_txn",
-            timeout(
-                Duration::from_millis(self.mempool_txn_pull_timeout_ms),
-                callback_rcv
-            )
-            .await
-        ) {
-            Err(_) => Err(anyhow::anyhow!(
-                "[quorum_store] did not receive GetBatchResponse on time"
-            )),
-            Ok(resp) => match resp.map_err(anyhow::Error::from)?? {
-                QuorumStoreResponse::GetBatchResponse(txns) => Ok(txns),
-                _ => Err(anyhow::anyhow!(
-                    "[quorum_store] did not receive expected GetBatchResponse"
-                )),
+            Self::await_pull(callback_rcv, self.pull_deadline(), max_bytes).await
+        )
+    }
+
+    /// Same as `pull_internal`, but if fewer than min_items txns are available, mempool waits up
+    /// to max_wait for more txns before responding. Under light load this fills batches better,
+    /// while under heavy load mempool responds right away.
+    pub async fn pull_internal_min_items(
+        &self,
+        max_items: u64,
+        max_bytes: u64,
+        min_items: u64,
+        max_wait: Duration,
+        exclude_transactions: BTreeMap<TransactionSummary, TransactionInProgress>,
+    ) -> Result<Vec<SignedTransaction>, MempoolPullError> {
+        let callback_rcv = self.send_pull_request(
+            max_items,
+            max_bytes,
+            exclude_transactions,
+            min_items,
+            max_wait,
+        )?;
+        // mempool holds on to the request for up to max_wait before responding
+        monitor!(
+            "pull_txn",
+            Self::await_pull(callback_rcv, self.pull_deadline() + max_wait, max_bytes).await
+        )
+    }
+
+    fn send_pull_request(
+        &self,
+        max_items: u64,
+        max_bytes: u64,
+        exclude_transactions: BTreeMap<TransactionSummary, TransactionInProgress>,
+        min_items: u64,
+        max_wait: Duration,
+    ) -> Result<oneshot::Receiver<anyhow::Result<QuorumStoreResponse>>, MempoolPullError> {
+        let (callback, callback_rcv) = oneshot::channel();
+        let msg = QuorumStoreRequest::GetBatchRequest {
+            max_txns: max_items,
+            max_bytes,
+            return_non_full: true,
+            exclude_transactions,
+            min_txns: min_items,
+            max_wait,
+            callback,
+        };
+        self.mempool_tx.lock().try_send(msg).map_err(|e| {
+            if e.is_full() {
+                // the caller should back off rather than retry right away
+                MempoolPullError::ChannelFull
+            } else {
+                MempoolPullError::SendFailed(e.to_string())
+            }
+        })?;
+        Ok(callback_rcv)
+    }
+
+    fn pull_deadline(&self) -> Instant {
+        Instant::now() + Duration::from_millis(self.mempool_txn_pull_timeout_ms)
+    }
+
+    /// Waits for the response to a pull until deadline, recording the pull latency and timeouts.
+    async fn await_pull(
+        callback_rcv: oneshot::Receiver<anyhow::Result<QuorumStoreResponse>>,
+        deadline: Instant,
+        max_bytes: u64,
+    ) -> Result<Vec<SignedTransaction>, MempoolPullError> {
+        let start = Instant::now();
+        let result = Self::pulled_txns(timeout_at(deadline, callback_rcv).await, max_bytes);
+        counters::MEMPOOL_PULL_LATENCY.observe(start.elapsed().as_secs_f64());
+        if let Err(MempoolPullError::Timeout) = result {
+            counters::MEMPOOL_PULL_TIMEOUT_COUNT.inc();
+        }
+        result
+    }
+
+    fn pulled_txns(
+        response: Result<Result<anyhow::Result<QuorumStoreResponse>, oneshot::Canceled>, Elapsed>,
+        max_bytes: u64,
+    ) -> Result<Vec<SignedTransaction>, MempoolPullError> {
+        match response
+            .map_err(|_| MempoolPullError::Timeout)?
+            .map_err(|_| MempoolPullError::Canceled)?
+            .map_err(|e| MempoolPullError::Mempool(Arc::new(e)))?
+        {
+            QuorumStoreResponse::GetBatchResponse(txns) => {
+                // don't trust mempool to honor max_bytes, as the pulled txns go straight into batches
+                let num_bytes: u64 = txns.iter().map(|txn| txn.txn_bytes_len() as u64).sum();
+                if num_bytes > max_bytes {
+                    warn!(
+                        "QS: mempool returned {} txns of {} bytes, more than max {} bytes",
+                        txns.len(),
+                        num_bytes,
+                        max_bytes
+                    );
+                    return Err(MempoolPullError::ExceededMaxBytes {
+                        max_bytes,
+                        num_bytes,
+                    });
+                }
+                Ok(txns)
             },
+            _ => Err(MempoolPullError::UnexpectedResponse),
         }
     }
 }
@@ -314,6 +314,13 @@ where
 
 // Creates a single test transaction for a random account
 pub(crate) fn create_signed_transaction(gas_unit_price: u64) -> SignedTransaction {
+    create_signed_transaction_with_expiration(gas_unit_price, 0)
+}
+
+pub(crate) fn create_signed_transaction_with_expiration(
+    gas_unit_price: u64,
+    expiration_timestamp_secs: u64,
+) -> SignedTransaction {
     let private_key = Ed25519PrivateKey::generate_for_testing();
     let public_key = private_key.public_key();
 
@@ -325,7 +332,7 @@ pub(crate) fn create_signed_transaction(gas_unit_price: u64) -> SignedTransactio
         transaction_payload,
         0,
         gas_unit_price,
-        0,
+        expiration_timestamp_secs,
         ChainId::new(10),
     );
     SignedTransaction::new(
@@ -41,6 +41,9 @@ pub enum ExecutorError {
 
     #[error("request timeout")]
     CouldNotGetData,
+
+    #[error("request cancelled due to shutdown")]
+    ShuttingDown,
 }
 
 impl From<anyhow::Error> for ExecutorError {
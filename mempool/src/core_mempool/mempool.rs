@@ -428,7 +428,7 @@ impl Mempool {
         max_txns: u64,
         max_bytes: u64,
         return_non_full: bool,
-        exclude_transactions: BTreeMap<TransactionSummary, TransactionInProgress>,
+        exclude_transactions: &BTreeMap<TransactionSummary, TransactionInProgress>,
     ) -> Vec<SignedTransaction> {
         let start_time = Instant::now();
         let exclude_size = exclude_transactions.len();
@@ -463,7 +463,7 @@ impl Mempool {
                             txn.address,
                             txn_seq - 1,
                             &inserted,
-                            &exclude_transactions,
+                            exclude_transactions,
                         );
                     let account_sequence_number =
                         self.transactions.get_account_sequence_number(&txn.address);
@@ -661,4 +661,8 @@ impl Mempool {
     pub fn get_parking_lot_addresses(&self) -> Vec<(AccountAddress, u64)> {
         self.transactions.get_parking_lot_addresses()
     }
+
+    pub(crate) fn num_ready_txns(&self) -> usize {
+        self.transactions.num_ready_txns()
+    }
 }
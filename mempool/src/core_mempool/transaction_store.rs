@@ -1044,6 +1044,11 @@ impl TransactionStore {
         self.parking_lot_index.size()
     }
 
+    /// Number of transactions that are ready to be pulled into a batch.
+    pub(crate) fn num_ready_txns(&self) -> usize {
+        self.priority_index.size()
+    }
+
     #[cfg(test)]
     pub(crate) fn get_transactions(&self) -> &HashMap<AccountAddress, AccountTransactions> {
         &self.transactions
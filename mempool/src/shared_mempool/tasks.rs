@@ -21,7 +21,9 @@ use crate::{
 };
 use anyhow::Result;
 use aptos_config::network_id::PeerNetworkId;
-use aptos_consensus_types::common::RejectedTransactionSummary;
+use aptos_consensus_types::common::{
+    RejectedTransactionSummary, TransactionInProgress, TransactionSummary,
+};
 use aptos_crypto::HashValue;
 use aptos_infallible::{Mutex, RwLock};
 use aptos_logger::prelude::*;
@@ -41,10 +43,11 @@ use futures::{channel::oneshot, stream::FuturesUnordered};
 use rayon::prelude::*;
 use std::{
     cmp,
+    collections::BTreeMap,
     sync::Arc,
     time::{Duration, Instant},
 };
-use tokio::runtime::Handle;
+use tokio::{runtime::Handle, sync::Notify};
 // ============================== //
 //  broadcast_coordinator tasks  //
 // ============================== //
@@ -378,6 +381,13 @@ where
         &mut statuses,
         client_submitted,
     );
+    // only wake up the GetBatchRequests waiting for more transactions if there are more
+    if statuses
+        .iter()
+        .any(|(_, (status, _))| status.code == MempoolStatusCode::Accepted)
+    {
+        smp.txns_added.notify_waiters();
+    }
     notify_subscribers(SharedMempoolNotification::NewTransactions, &smp.subscribers);
     statuses
 }
@@ -555,44 +565,54 @@ pub(crate) fn process_quorum_store_request<NetworkClient, TransactionValidator>(
     let start_time = Instant::now();
 
     let (resp, callback, counter_label) = match req {
-        QuorumStoreRequest::GetBatchRequest(
+        QuorumStoreRequest::GetBatchRequest {
             max_txns,
             max_bytes,
             return_non_full,
             exclude_transactions,
+            min_txns,
+            max_wait,
             callback,
-        ) => {
-            let txns;
-            {
-                let lock_timer = counters::mempool_service_start_latency_timer(
-                    counters::GET_BLOCK_LOCK_LABEL,
-                    counters::REQUEST_SUCCESS_LABEL,
-                );
-                let mut mempool = smp.mempool.lock();
-                lock_timer.observe_duration();
-
-                {
-                    let _gc_timer = counters::mempool_service_start_latency_timer(
-                        counters::GET_BLOCK_GC_LABEL,
-                        counters::REQUEST_SUCCESS_LABEL,
-                    );
-                    // gc before pulling block as extra protection against txns that may expire in consensus
-                    // Note: this gc operation relies on the fact that consensus uses the system time to determine block timestamp
-                    let curr_time = aptos_infallible::duration_since_epoch();
-                    mempool.gc_by_expiration_time(curr_time);
-                }
-
-                let max_txns = cmp::max(max_txns, 1);
-                let _get_batch_timer = counters::mempool_service_start_latency_timer(
-                    counters::GET_BLOCK_GET_BATCH_LABEL,
-                    counters::REQUEST_SUCCESS_LABEL,
-                );
-                txns =
-                    mempool.get_batch(max_txns, max_bytes, return_non_full, exclude_transactions);
-            }
+        } => {
+            let max_txns = cmp::max(max_txns, 1);
+            let (txns, num_ready_txns) = get_batch(
+                &smp.mempool,
+                max_txns,
+                max_bytes,
+                return_non_full,
+                &exclude_transactions,
+            );
 
             // mempool_service_transactions is logged inside get_batch
 
+            if (txns.len() as u64) < min_txns.min(max_txns) && !max_wait.is_zero() {
+                // respond once more transactions are added, without blocking the coordinator
+                let mempool = smp.mempool.clone();
+                let txns_added = smp.txns_added.clone();
+                tokio::spawn(async move {
+                    let txns = wait_for_batch(
+                        &mempool,
+                        &txns_added,
+                        txns,
+                        num_ready_txns,
+                        max_txns,
+                        max_bytes,
+                        return_non_full,
+                        &exclude_transactions,
+                        min_txns,
+                        Instant::now() + max_wait,
+                    )
+                    .await;
+                    respond_to_quorum_store(
+                        QuorumStoreResponse::GetBatchResponse(txns),
+                        callback,
+                        counters::GET_BLOCK_LABEL,
+                        start_time,
+                    );
+                });
+                return;
+            }
+
             (
                 QuorumStoreResponse::GetBatchResponse(txns),
                 callback,
@@ -612,6 +632,92 @@ pub(crate) fn process_quorum_store_request<NetworkClient, TransactionValidator>(
             )
         },
     };
+    respond_to_quorum_store(resp, callback, counter_label, start_time);
+}
+
+/// Also returns the number of transactions ready to be pulled, as of the pull.
+fn get_batch(
+    mempool: &Mutex<CoreMempool>,
+    max_txns: u64,
+    max_bytes: u64,
+    return_non_full: bool,
+    exclude_transactions: &BTreeMap<TransactionSummary, TransactionInProgress>,
+) -> (Vec<SignedTransaction>, usize) {
+    let lock_timer = counters::mempool_service_start_latency_timer(
+        counters::GET_BLOCK_LOCK_LABEL,
+        counters::REQUEST_SUCCESS_LABEL,
+    );
+    let mut mempool = mempool.lock();
+    lock_timer.observe_duration();
+
+    {
+        let _gc_timer = counters::mempool_service_start_latency_timer(
+            counters::GET_BLOCK_GC_LABEL,
+            counters::REQUEST_SUCCESS_LABEL,
+        );
+        // gc before pulling block as extra protection against txns that may expire in consensus
+        // Note: this gc operation relies on the fact that consensus uses the system time to determine block timestamp
+        let curr_time = aptos_infallible::duration_since_epoch();
+        mempool.gc_by_expiration_time(curr_time);
+    }
+
+    let _get_batch_timer = counters::mempool_service_start_latency_timer(
+        counters::GET_BLOCK_GET_BATCH_LABEL,
+        counters::REQUEST_SUCCESS_LABEL,
+    );
+    let txns = mempool.get_batch(max_txns, max_bytes, return_non_full, exclude_transactions);
+    (txns, mempool.num_ready_txns())
+}
+
+/// Pulls a batch again when more transactions are ready than num_ready_txns at the time txns were
+/// pulled, until it has at least min_txns transactions or the deadline passed, and returns the
+/// last batch pulled.
+async fn wait_for_batch(
+    mempool: &Mutex<CoreMempool>,
+    txns_added: &Notify,
+    mut txns: Vec<SignedTransaction>,
+    mut num_ready_txns: usize,
+    max_txns: u64,
+    max_bytes: u64,
+    return_non_full: bool,
+    exclude_transactions: &BTreeMap<TransactionSummary, TransactionInProgress>,
+    min_txns: u64,
+    deadline: Instant,
+) -> Vec<SignedTransaction> {
+    loop {
+        let notified = txns_added.notified();
+        tokio::pin!(notified);
+        // register before checking, so that transactions added right after the check wake us up
+        notified.as_mut().enable();
+        // pulling takes the mempool lock and gcs, so it's skipped unless more transactions are
+        // ready, e.g. not when the added transactions are parked
+        let prev_num_ready_txns = num_ready_txns;
+        num_ready_txns = mempool.lock().num_ready_txns();
+        if num_ready_txns > prev_num_ready_txns {
+            (txns, num_ready_txns) = get_batch(
+                mempool,
+                max_txns,
+                max_bytes,
+                return_non_full,
+                exclude_transactions,
+            );
+            if txns.len() as u64 >= min_txns.min(max_txns) {
+                return txns;
+            }
+        }
+        tokio::select! {
+            _ = notified => {},
+            _ = tokio::time::sleep_until(deadline.into()) => return txns,
+        }
+    }
+}
+
+fn respond_to_quorum_store(
+    resp: QuorumStoreResponse,
+    callback: oneshot::Sender<Result<QuorumStoreResponse>>,
+    counter_label: &'static str,
+    start_time: Instant,
+) {
     // Send back to callback
     let result = if callback.send(Ok(resp)).is_err() {
         debug!(LogSchema::event_log(
@@ -38,9 +38,9 @@ use std::{
     pin::Pin,
     sync::Arc,
     task::Waker,
-    time::{Instant, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
-use tokio::runtime::Handle;
+use tokio::{runtime::Handle, sync::Notify};
 
 pub type MempoolSenderBucket = u8;
 pub type TimelineIndexIdentifier = u8;
@@ -56,6 +56,8 @@ pub(crate) struct SharedMempool<NetworkClient, TransactionValidator> {
     pub subscribers: Vec<UnboundedSender<SharedMempoolNotification>>,
     pub broadcast_within_validator_network: Arc<RwLock<bool>>,
     pub use_case_history: Arc<Mutex<UseCaseHistory>>,
+    // notified when transactions are added, to wake up GetBatchRequests waiting for more
+    pub txns_added: Arc<Notify>,
 }
 
 impl<
@@ -87,6 +89,7 @@ impl<
             subscribers,
             broadcast_within_validator_network: Arc::new(RwLock::new(true)),
             use_case_history: Arc::new(Mutex::new(use_case_history)),
+            txns_added: Arc::new(Notify::new()),
         }
     }
 
@@ -172,18 +175,21 @@ impl Future for ScheduledBroadcast {
 
 /// Message sent from QuorumStore to Mempool.
 pub enum QuorumStoreRequest {
-    GetBatchRequest(
+    GetBatchRequest {
         // max batch size
-        u64,
+        max_txns: u64,
         // max byte size
-        u64,
-        // return non full
-        bool,
+        max_bytes: u64,
+        return_non_full: bool,
         // transactions to exclude from the requested batch
-        BTreeMap<TransactionSummary, TransactionInProgress>,
+        exclude_transactions: BTreeMap<TransactionSummary, TransactionInProgress>,
+        // min batch size to wait for, if fewer transactions are available
+        min_txns: u64,
+        // max time to wait for the min batch size before responding with what is available
+        max_wait: Duration,
         // callback to respond to
-        oneshot::Sender<Result<QuorumStoreResponse>>,
-    ),
+        callback: oneshot::Sender<Result<QuorumStoreResponse>>,
+    },
     // TODO: Do we use it in the real QS as well?
     /// Notifications about *rejected* committed txns.
     RejectNotification(
@@ -197,19 +203,23 @@ pub enum QuorumStoreRequest {
 impl fmt::Display for QuorumStoreRequest {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let payload = match self {
-            QuorumStoreRequest::GetBatchRequest(
+            QuorumStoreRequest::GetBatchRequest {
                 max_txns,
                 max_bytes,
                 return_non_full,
-                excluded_txns,
-                _,
-            ) => {
+                exclude_transactions,
+                min_txns,
+                max_wait,
+                ..
+            } => {
                 format!(
-                    "GetBatchRequest [max_txns: {}, max_bytes: {}, return_non_full: {}, excluded_txns_length: {}]",
+                    "GetBatchRequest [max_txns: {}, max_bytes: {}, return_non_full: {}, excluded_txns_length: {}, min_txns: {}, max_wait: {:?}]",
                     max_txns,
                     max_bytes,
                     return_non_full,
-                    excluded_txns.len()
+                    exclude_transactions.len(),
+                    min_txns,
+                    max_wait
                 )
             },
             QuorumStoreRequest::RejectNotification(rejected_txns, _) => {
@@ -255,7 +255,7 @@ impl ConsensusMock {
         max_txns: u64,
         max_bytes: u64,
     ) -> Vec<SignedTransaction> {
-        let block = mempool.get_batch(max_txns, max_bytes, true, self.0.clone());
+        let block = mempool.get_batch(max_txns, max_bytes, true, &self.0);
         block.iter().for_each(|t| {
             let txn_summary =
                 TransactionSummary::new(t.sender(), t.replay_protector(), t.committed_hash());
@@ -540,7 +540,7 @@ fn test_system_ttl() {
 
     // GC routine should clear transaction from first insert but keep last one.
     mempool.gc();
-    let batch = mempool.get_batch(1, 1024, true, btreemap![]);
+    let batch = mempool.get_batch(1, 1024, true, &btreemap![]);
     assert_eq!(vec![transaction.make_signed_transaction()], batch);
 }
 
@@ -556,14 +556,14 @@ fn test_commit_callback() {
     )]);
 
     // Check that pool is empty.
-    assert!(pool.get_batch(1, 1024, true, btreemap![]).is_empty());
+    assert!(pool.get_batch(1, 1024, true, &btreemap![]).is_empty());
     // Transaction 5 got back from consensus.
     pool.commit_transaction(
         &TestTransaction::get_address(1),
         ReplayProtector::SequenceNumber(5),
     );
     // Verify that we can execute transaction 6.
-    assert_eq!(pool.get_batch(1, 1024, true, btreemap![])[0], txns[0]);
+    assert_eq!(pool.get_batch(1, 1024, true, &btreemap![])[0], txns[0]);
 }
 
 #[test]
@@ -1154,7 +1154,7 @@ fn test_parking_lot_eviction() {
     }
     // Make sure that we have correct txns in Mempool.
     let mut txns: Vec<_> = pool
-        .get_batch(5, 5120, true, btreemap![])
+        .get_batch(5, 5120, true, &btreemap![])
         .iter()
         .map(SignedTransaction::sequence_number)
         .collect();
@@ -1310,7 +1310,7 @@ fn test_parking_lot_evict_only_for_ready_txn_insertion() {
 
     // Make sure that we have correct txns in Mempool.
     let mut txns: Vec<_> = pool
-        .get_batch(5, 5120, true, btreemap![])
+        .get_batch(5, 5120, true, &btreemap![])
         .iter()
         .map(SignedTransaction::sequence_number)
         .collect();
@@ -1379,7 +1379,7 @@ fn test_gc_ready_transaction() {
     pool.gc_by_expiration_time(Duration::from_secs(1));
 
     // Make sure txns 2 and 3 became not ready and we can't read them from any API.
-    let block = pool.get_batch(1, 1024, true, btreemap![]);
+    let block = pool.get_batch(1, 1024, true, &btreemap![]);
     assert_eq!(block.len(), 1);
     assert_eq!(block[0].sequence_number(), 0);
 
@@ -1433,7 +1433,7 @@ fn test_clean_stuck_transactions() {
         None,
         Some(BroadcastPeerPriority::Primary),
     );
-    let block = pool.get_batch(1, 1024, true, btreemap![]);
+    let block = pool.get_batch(1, 1024, true, &btreemap![]);
     assert_eq!(block.len(), 1);
     assert_eq!(block[0].sequence_number(), 10);
 }
@@ -1513,15 +1513,15 @@ fn test_bytes_limit() {
         )
         .unwrap();
     }
-    let get_all = pool.get_batch(100, 100 * 1024, true, btreemap![]);
+    let get_all = pool.get_batch(100, 100 * 1024, true, &btreemap![]);
     assert_eq!(get_all.len(), 100);
     let txn_size = get_all[0].txn_bytes_len() as u64;
     let limit = 10;
-    let hit_limit = pool.get_batch(100, txn_size * limit, true, btreemap![]);
+    let hit_limit = pool.get_batch(100, txn_size * limit, true, &btreemap![]);
     assert_eq!(hit_limit.len(), limit as usize);
-    let hit_limit = pool.get_batch(100, txn_size * limit + 1, true, btreemap![]);
+    let hit_limit = pool.get_batch(100, txn_size * limit + 1, true, &btreemap![]);
     assert_eq!(hit_limit.len(), limit as usize);
-    let hit_limit = pool.get_batch(100, txn_size * limit - 1, true, btreemap![]);
+    let hit_limit = pool.get_batch(100, txn_size * limit - 1, true, &btreemap![]);
     assert_eq!(hit_limit.len(), limit as usize - 1);
 }
 
@@ -1609,7 +1609,7 @@ fn test_sequence_number_behavior_at_capacity() {
         ReplayProtector::SequenceNumber(0),
     );
 
-    let batch = pool.get_batch(10, 10240, true, btreemap![]);
+    let batch = pool.get_batch(10, 10240, true, &btreemap![]);
     assert_eq!(batch.len(), 1);
 }
 
@@ -1629,7 +1629,7 @@ fn test_sequence_number_stale_account_sequence_number() {
     )
     .unwrap();
 
-    let batch = pool.get_batch(10, 10240, true, btreemap![]);
+    let batch = pool.get_batch(10, 10240, true, &btreemap![]);
     assert_eq!(batch.len(), 1);
 }
 
@@ -1646,48 +1646,48 @@ fn test_not_return_non_full() {
     add_txn(&mut pool, txn_1).unwrap();
 
     // doesn't hit any limits
-    let batch = pool.get_batch(10, 10240, true, btreemap![]);
+    let batch = pool.get_batch(10, 10240, true, &btreemap![]);
     assert_eq!(batch.len(), 2);
 
-    let batch = pool.get_batch(10, 10240, false, btreemap![]);
+    let batch = pool.get_batch(10, 10240, false, &btreemap![]);
     assert_eq!(batch.len(), 0);
 
     // reaches or close to max_txns
-    let batch = pool.get_batch(txn_num + 1, 10240, false, btreemap![]);
+    let batch = pool.get_batch(txn_num + 1, 10240, false, &btreemap![]);
     assert_eq!(batch.len(), 0);
 
-    let batch = pool.get_batch(txn_num, 10240, false, btreemap![]);
+    let batch = pool.get_batch(txn_num, 10240, false, &btreemap![]);
     assert_eq!(batch.len(), 2);
 
-    let batch = pool.get_batch(txn_num - 1, 10240, false, btreemap![]);
+    let batch = pool.get_batch(txn_num - 1, 10240, false, &btreemap![]);
     assert_eq!(batch.len(), 1);
 
-    let batch = pool.get_batch(txn_num + 1, 10240, true, btreemap![]);
+    let batch = pool.get_batch(txn_num + 1, 10240, true, &btreemap![]);
     assert_eq!(batch.len(), 2);
 
-    let batch = pool.get_batch(txn_num, 10240, true, btreemap![]);
+    let batch = pool.get_batch(txn_num, 10240, true, &btreemap![]);
     assert_eq!(batch.len(), 2);
 
-    let batch = pool.get_batch(txn_num - 1, 10240, true, btreemap![]);
+    let batch = pool.get_batch(txn_num - 1, 10240, true, &btreemap![]);
     assert_eq!(batch.len(), 1);
 
     // reaches or close to max_bytes
-    let batch = pool.get_batch(10, txn_bytes + 1, false, btreemap![]);
+    let batch = pool.get_batch(10, txn_bytes + 1, false, &btreemap![]);
     assert_eq!(batch.len(), 0);
 
-    let batch = pool.get_batch(10, txn_bytes, false, btreemap![]);
+    let batch = pool.get_batch(10, txn_bytes, false, &btreemap![]);
     assert_eq!(batch.len(), 2);
 
-    let batch = pool.get_batch(10, txn_bytes - 1, false, btreemap![]);
+    let batch = pool.get_batch(10, txn_bytes - 1, false, &btreemap![]);
     assert_eq!(batch.len(), 1);
 
-    let batch = pool.get_batch(10, txn_bytes + 1, true, btreemap![]);
+    let batch = pool.get_batch(10, txn_bytes + 1, true, &btreemap![]);
     assert_eq!(batch.len(), 2);
 
-    let batch = pool.get_batch(10, txn_bytes, true, btreemap![]);
+    let batch = pool.get_batch(10, txn_bytes, true, &btreemap![]);
     assert_eq!(batch.len(), 2);
 
-    let batch = pool.get_batch(10, txn_bytes - 1, true, btreemap![]);
+    let batch = pool.get_batch(10, txn_bytes - 1, true, &btreemap![]);
     assert_eq!(batch.len(), 1);
 }
 
@@ -1716,7 +1716,7 @@ fn test_include_gas_upgraded() {
         ReplayProtector::SequenceNumber(low_gas_signed_txn.sequence_number()),
         low_gas_signed_txn.committed_hash(),
     );
-    let batch = pool.get_batch(10, 10240, true, btreemap! {
+    let batch = pool.get_batch(10, 10240, true, &btreemap! {
         low_gas_txn => TransactionInProgress::new(low_gas_price)
     });
     assert_eq!(batch.len(), 0);
@@ -1738,7 +1738,7 @@ fn test_include_gas_upgraded() {
     );
 
     // When the low gas txn (but not the high gas txn) is excluded, will the high gas txn be included.
-    let batch = pool.get_batch(10, 10240, true, btreemap! {
+    let batch = pool.get_batch(10, 10240, true, &btreemap! {
         low_gas_txn => TransactionInProgress::new(low_gas_price)
     });
     assert_eq!(batch.len(), 1);
@@ -1749,12 +1749,12 @@ fn test_include_gas_upgraded() {
     assert_eq!(batch[0].sequence_number(), sequence_number);
     assert_eq!(batch[0].gas_unit_price(), high_gas_price);
 
-    let batch = pool.get_batch(10, 10240, true, btreemap! {
+    let batch = pool.get_batch(10, 10240, true, &btreemap! {
         high_gas_txn => TransactionInProgress::new(high_gas_price)
     });
     assert_eq!(batch.len(), 0);
 
-    let batch = pool.get_batch(10, 10240, true, btreemap! {
+    let batch = pool.get_batch(10, 10240, true, &btreemap! {
         low_gas_txn => TransactionInProgress::new(low_gas_price),
         high_gas_txn => TransactionInProgress::new(high_gas_price)
     });
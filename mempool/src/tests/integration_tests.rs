@@ -2,9 +2,12 @@
 // Parts of the project are originally copyright © Meta Platforms, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::tests::{
-    common::TestTransaction,
-    test_framework::{test_transaction, MempoolNode, MempoolTestFrameworkBuilder},
+use crate::{
+    tests::{
+        common::TestTransaction,
+        test_framework::{test_transaction, MempoolNode, MempoolTestFrameworkBuilder},
+    },
+    QuorumStoreRequest, QuorumStoreResponse,
 };
 use aptos_config::network_id::PeerNetworkId;
 use aptos_netcore::transport::ConnectionOrigin;
@@ -20,8 +23,9 @@ use aptos_network::{
     ProtocolId,
 };
 use aptos_types::transaction::ReplayProtector;
+use futures::{channel::oneshot, SinkExt};
 use once_cell::sync::Lazy;
-use std::time::Duration;
+use std::{collections::BTreeMap, time::Duration};
 
 const ALL_PROTOCOLS: [ProtocolId; 1] = [ProtocolId::MempoolDirectSend];
 static ALL_TXNS: Lazy<Vec<TestTransaction>> =
@@ -406,3 +410,51 @@ async fn fn_to_val_test() {
         val.assert_only_txns_in_mempool(&ALL_TXNS);
     }
 }
+
+/// Tests that a batch request waits for the min batch size, but not longer than the max wait
+#[tokio::test]
+async fn test_get_batch_min_txns() {
+    let mut node = MempoolTestFrameworkBuilder::single_validator();
+    node.add_txns_via_client(&TXN_1).await;
+
+    // Fewer txns than requested, so the response waits until more are added
+    let (callback, callback_rcv) = oneshot::channel();
+    node.consensus_to_mempool_sender
+        .clone()
+        .send(QuorumStoreRequest::GetBatchRequest {
+            max_txns: 100,
+            max_bytes: 102400,
+            return_non_full: true,
+            exclude_transactions: BTreeMap::new(),
+            min_txns: 2,
+            max_wait: Duration::from_secs(10),
+            callback,
+        })
+        .await
+        .unwrap();
+    node.add_txns_via_client(&TXN_2).await;
+    match callback_rcv.await.unwrap().unwrap() {
+        QuorumStoreResponse::GetBatchResponse(txns) => assert_eq!(txns.len(), 2),
+        _ => panic!("Unexpected response"),
+    }
+
+    // More txns than mempool has, so the response returns what's available after the max wait
+    let (callback, callback_rcv) = oneshot::channel();
+    node.consensus_to_mempool_sender
+        .clone()
+        .send(QuorumStoreRequest::GetBatchRequest {
+            max_txns: 100,
+            max_bytes: 102400,
+            return_non_full: true,
+            exclude_transactions: BTreeMap::new(),
+            min_txns: 3,
+            max_wait: Duration::from_millis(100),
+            callback,
+        })
+        .await
+        .unwrap();
+    match callback_rcv.await.unwrap().unwrap() {
+        QuorumStoreResponse::GetBatchResponse(txns) => assert_eq!(txns.len(), 2),
+        _ => panic!("Unexpected response"),
+    }
+}
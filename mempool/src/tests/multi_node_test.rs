@@ -364,7 +364,7 @@ impl TestHarness {
             let block = self
                 .node(sender_id)
                 .mempool()
-                .get_batch(100, 102400, true, btreemap![]);
+                .get_batch(100, 102400, true, &btreemap![]);
             for txn in transactions.iter() {
                 assert!(block.contains(txn));
             }
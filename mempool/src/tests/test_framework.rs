@@ -184,7 +184,7 @@ impl MempoolNode {
             let block = self
                 .mempool
                 .lock()
-                .get_batch(100, 102400, true, btreemap![]);
+                .get_batch(100, 102400, true, &btreemap![]);
 
             if block_contains_all_transactions(&block, txns) {
                 break;
@@ -243,7 +243,7 @@ impl MempoolNode {
         let block = self
             .mempool
             .lock()
-            .get_batch(100, 102400, true, btreemap![]);
+            .get_batch(100, 102400, true, &btreemap![]);
         if !condition(&block, txns) {
             let actual: Vec<_> = block
                 .iter()